@@ -0,0 +1,5 @@
+/// stdio で LSP を話す satysfi-fmt の language server
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().expect("failed to start the tokio runtime");
+    runtime.block_on(satysfi_formatter::lsp::run_stdio());
+}
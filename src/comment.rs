@@ -1,5 +1,5 @@
 use satysfi_parser::{Cst, CstText, Rule, Span};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 #[derive(Clone)]
 pub struct Comment {
@@ -7,103 +7,323 @@ pub struct Comment {
     pub span: Span,
 }
 
+/// formatter の動作を切り替えるコメント
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Directive {
+    /// `% satysfi-fmt: off`: ここから元の文字列のまま出力する
+    Off,
+    /// `% satysfi-fmt: on`: ここから再び整形する
+    On,
+    /// `% satysfi-fmt: skip`: 次の要素を元の文字列のまま出力する
+    Skip,
+}
+
+impl Comment {
+    /// `% satysfi-fmt: <指示>` の形のコメントなら、その指示を返す
+    pub fn directive(&self) -> Option<Directive> {
+        let body = self.text.trim_start_matches('%').trim();
+        let (key, value) = body.split_once(':')?;
+        if key.trim_end() != "satysfi-fmt" {
+            return None;
+        }
+        match value.trim() {
+            "off" => Some(Directive::Off),
+            "on" => Some(Directive::On),
+            "skip" => Some(Directive::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// 文書中のコメントを前から順に返す
+/// 文字列リテラル (`` `...` ``, ``` ``...`` ``` など) の中の `%` や、
+/// `\%` のようにエスケープされた `%` はコメントとして扱わない
 pub fn get_comments(csttext: &CstText) -> VecDeque<Comment> {
+    let text = csttext.text.as_str();
     let mut comments = VecDeque::new();
-    // 全ての行を確認する
-    for (index, line) in csttext.lines.iter().enumerate() {
-        if index == 0 {
-            continue;
-        }
-        let start = csttext.lines[index - 1];
-        let end = *line;
-        let text = csttext.get_text_from_span(Span { start, end });
-        if text.trim_start().starts_with("@require") || text.trim_start().starts_with("@import") {
-            // @require, @import の行ではコメントではない
-            continue;
-        };
-        if let Some(inner) = text.find('%') {
-            // check whether percent is escaped
-            let is_escaped = {
-                let text_removed_backslash = text[..inner].trim_matches('\\');
-                (text[..inner].len() - text_removed_backslash.len()) % 2 == 1
-            };
-            if is_escaped {
-                continue;
+    let mut line_start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\n' => line_start = index + 1,
+            // エスケープされた文字は読み飛ばす
+            '\\' => {
+                chars.next_if(|(_, c)| *c != '\n');
             }
-            let comment = format!("% {}", &text[inner + 1..].trim_start());
-
-            comments.push_back(Comment {
-                text: comment,
-                // 行内部の開始位置を足す
-                span: Span {
-                    start: start + inner,
-                    end,
-                },
-            });
+            '`' => {
+                let count = text[index..].len() - text[index..].trim_start_matches('`').len();
+                // 同じ数の `` ` `` で閉じられていない場合は文字列リテラルとみなさない
+                if let Some(end) = string_literal_end(text, index, count) {
+                    line_start = text[..end]
+                        .rfind('\n')
+                        .map_or(line_start, |index| index + 1);
+                    while chars.next_if(|(index, _)| *index < end).is_some() {}
+                } else {
+                    while chars.next_if(|(_, c)| *c == '`').is_some() {}
+                }
+            }
+            '%' => {
+                let line = text[line_start..].trim_start();
+                if line.starts_with("@require") || line.starts_with("@import") {
+                    // @require, @import の行ではコメントではない
+                    continue;
+                }
+                // 改行文字までをコメントとする
+                let end = text[index..]
+                    .find('\n')
+                    .map_or(text.len(), |newline| index + newline + 1);
+                comments.push_back(Comment {
+                    text: format!("% {}", text[index + 1..end].trim_start()),
+                    span: Span { start: index, end },
+                });
+                line_start = end;
+                while chars.next_if(|(index, _)| *index < end).is_some() {}
+            }
+            _ => {}
         }
     }
     comments
 }
 
-fn check_comment(cst: &Cst, comment: &Comment) -> bool {
-    // headers は例外
-    let inner_contain_comment = cst
-        .inner
-        .iter()
-        .any(|inner_cst| inner_cst.rule != Rule::headers && inner_cst.span.contains(&comment.span));
-    let contain_comment = cst.span.contains(&comment.span);
-    // コメントを含みかつコメントが内部の要素に含まれていない場合出力する
-    contain_comment && !inner_contain_comment && cst.rule != Rule::headers
+/// `start` から始まる `count` 個の `` ` `` で開く文字列リテラルの終了位置
+/// 連続する `` ` `` の数がちょうど `count` 個の部分で閉じる
+fn string_literal_end(text: &str, start: usize, count: usize) -> Option<usize> {
+    let mut offset = start + count;
+    while let Some(index) = text[offset..].find('`') {
+        let open = offset + index;
+        let len = text[open..].len() - text[open..].trim_start_matches('`').len();
+        if len == count {
+            return Some(open + len);
+        }
+        offset = open + len;
+    }
+    None
 }
 
-/// csttext にコメントを追加して組み直す関数
-pub fn csttext_insert_comments(csttext: CstText) -> CstText {
-    let mut comments = get_comments(&csttext);
-    if let Some(comment) = comments.pop_front() {
-        comments.push_front(comment.clone());
-        check_comment(&csttext.cst, &comment);
-        let mut csttext = csttext;
-        cst_insert_comment(&mut csttext.cst, &mut comments);
-        csttext
-    } else {
-        csttext
-    }
+/// コメントを付ける位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// 要素の前 (直前の行など)
+    Leading,
+    /// 要素と同じ行の末尾
+    Trailing,
+    /// 前後に子要素がない位置 (最後の子要素の後ろの行や、空の括弧の中など)
+    Dangling,
+}
+
+/// 元の文字列をそのまま出力する規則
+/// 中に含まれるコメントも元の文字列のまま出力されるので、要素には付けない
+const SELF_TEXT_RULES: [Rule; 8] = [
+    Rule::arg,
+    Rule::pattern,
+    Rule::pkgname,
+    Rule::const_string,
+    Rule::math_cmd,
+    Rule::horizontal_list,
+    Rule::expr_with_mod,
+    Rule::dummy_inline_cmd_incomplete,
+];
+
+/// 要素を並べる規則
+/// `% satysfi-fmt: skip` はこの規則の要素全体ではなく、最初の子要素に付ける
+const SEQUENCE_RULES: [Rule; 4] = [
+    Rule::preamble,
+    Rule::vertical,
+    Rule::horizontal_single,
+    Rule::math_single,
+];
+
+/// 要素に付けたコメント
+struct Attached {
+    /// 付けた要素の規則 (同じ span の要素を区別する)
+    rule: Rule,
+    placement: Placement,
+    comment: Comment,
 }
 
-/// コメントの挿入をDFSで行う
-fn cst_insert_comment(cst: &mut Cst, comments: &mut VecDeque<Comment>) {
-    let mut insert_comment = vec![];
-    for comment in comments.iter() {
-        let flag = check_comment(cst, comment);
-
-        if flag {
-            #[cfg(debug_assertions)]
-            println!("cst: {:?}, insert-comment: {:?}", cst.rule, comment.text);
-            insert_comment.push(Cst {
-                rule: Rule::comments,
-                inner: vec![],
-                span: comment.span,
+/// `% satysfi-fmt: off` から `% satysfi-fmt: on` までの範囲
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    /// off のコメントの span
+    pub off: Span,
+    /// on のコメントの span (なければ文書の最後まで)
+    pub on: Option<Span>,
+}
+
+/// cst の要素に付けたコメント
+#[derive(Default)]
+pub struct Trivia {
+    /// 要素の span の (start, end) ごとのコメント
+    attached: HashMap<(usize, usize), Vec<Attached>>,
+    /// 要素に付けた全てのコメントの span (前から順)
+    spans: Vec<Span>,
+    /// 整形しない範囲 (前から順)
+    regions: Vec<Region>,
+}
+
+impl Trivia {
+    /// 全てのコメントを cst の要素に付ける
+    pub fn new(csttext: &CstText) -> Self {
+        let mut trivia = Trivia::default();
+        let mut off: Option<Span> = None;
+        for comment in get_comments(csttext) {
+            let directive = comment.directive();
+            let span = comment.span;
+            // 元の文字列のまま出力される要素の中の指示は無視する
+            if !trivia.attach(&csttext.text, &csttext.cst, comment) {
+                continue;
+            }
+            match (directive, off) {
+                (Some(Directive::Off), None) => off = Some(span),
+                (Some(Directive::On), Some(start)) => {
+                    trivia.regions.push(Region {
+                        off: start,
+                        on: Some(span),
+                    });
+                    off = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(span) = off {
+            trivia.regions.push(Region {
+                off: span,
+                on: None,
             });
         }
+        // attach で行末の改行を除いたのに合わせる
+        for region in trivia.regions.iter_mut() {
+            for span in std::iter::once(&mut region.off).chain(region.on.as_mut()) {
+                span.end = span.start + csttext.text[span.start..span.end].trim_end().len();
+            }
+        }
+        trivia
     }
 
-    for inner_cst in cst.inner.iter_mut() {
-        // 再帰的に探索
-        cst_insert_comment(inner_cst, comments);
-    }
+    /// コメントを含む最も内側の要素の子要素に付ける
+    /// * 同じ行の直前に子要素があれば、その要素の Trailing
+    /// * 後ろに子要素があれば、その要素の Leading
+    /// * どちらもなければ、コメントを含む要素の Dangling
+    ///
+    /// `% satysfi-fmt: skip` は後ろに子要素があれば常にその要素 (要素の並びなら最初の要素) の Leading にする
+    ///
+    /// 元の文字列のまま出力される要素の中にあって付けなかった場合は false を返す
+    fn attach(&mut self, text: &str, cst: &Cst, comment: Comment) -> bool {
+        let mut parent = cst;
+        // headers は例外
+        while let Some(inner) = parent
+            .inner
+            .iter()
+            .find(|inner| inner.rule != Rule::headers && inner.span.contains(&comment.span))
+        {
+            if SELF_TEXT_RULES.contains(&inner.rule) {
+                return false;
+            }
+            parent = inner;
+        }
 
-    if !insert_comment.is_empty() {
-        insert_comment.iter().for_each(|comment| {
-            cst.inner.push(Cst {
-                rule: Rule::comments,
-                inner: vec![],
-                span: comment.span,
+        // 末尾の空白を除いた終了位置
+        let content_end = |inner: &Cst| {
+            inner.span.start + text[inner.span.start..inner.span.end].trim_end().len()
+        };
+        let before = parent
+            .inner
+            .iter()
+            .rev()
+            .find(|inner| content_end(inner) <= comment.span.start)
+            .filter(|inner| {
+                let gap = &text[content_end(inner)..comment.span.start];
+                gap.trim().is_empty() && !gap.contains('\n')
+            })
+            .filter(|_| comment.directive() != Some(Directive::Skip));
+        let mut after = parent
+            .inner
+            .iter()
+            .find(|inner| comment.span.end <= inner.span.start);
+        if comment.directive() == Some(Directive::Skip) {
+            while let Some(first) = after
+                .filter(|after| SEQUENCE_RULES.contains(&after.rule))
+                .and_then(|after| after.inner.first())
+            {
+                after = Some(first);
+            }
+        }
+        let (cst, placement) = match (before, after) {
+            (Some(before), _) => (before, Placement::Trailing),
+            (None, Some(after)) => (after, Placement::Leading),
+            (None, None) => (parent, Placement::Dangling),
+        };
+
+        // 行末の改行はコメントに含めない
+        let mut comment = comment;
+        comment.span.end =
+            comment.span.start + text[comment.span.start..comment.span.end].trim_end().len();
+        self.spans.push(comment.span);
+        self.attached
+            .entry((cst.span.start, cst.span.end))
+            .or_default()
+            .push(Attached {
+                rule: cst.rule,
+                placement,
+                comment,
             });
-            // insert した回数分減らせる
-            // comment は前から順番に並んでいる
-            comments.pop_front();
-        });
-        cst.inner.sort_by(|a, b| a.span.start.cmp(&b.span.start))
+        true
+    }
+
+    /// `cst` に `placement` の位置で付けたコメント
+    pub fn get(&self, cst: &Cst, placement: Placement) -> Vec<&Comment> {
+        self.attached
+            .get(&(cst.span.start, cst.span.end))
+            .into_iter()
+            .flatten()
+            .filter(|attached| attached.rule == cst.rule && attached.placement == placement)
+            .map(|attached| &attached.comment)
+            .collect()
+    }
+
+    /// `span` の中にコメントがあるか
+    pub fn contains(&self, span: Span) -> bool {
+        let index = self
+            .spans
+            .partition_point(|comment| comment.start < span.start);
+        self.spans
+            .get(index)
+            .is_some_and(|comment| comment.end <= span.end)
+    }
+
+    /// `% satysfi-fmt: skip` が付いていて、元の文字列のまま出力する要素か
+    pub fn is_skipped(&self, cst: &Cst) -> bool {
+        self.get(cst, Placement::Leading)
+            .iter()
+            .any(|comment| comment.directive() == Some(Directive::Skip))
+    }
+
+    /// `start` から始まる off のコメントで始まる範囲
+    pub fn region(&self, start: usize) -> Option<&Region> {
+        self.regions.iter().find(|region| region.off.start == start)
+    }
+
+    /// `start` から始まるコメントが整形しない範囲の端 (off か on) か
+    pub fn is_region_bound(&self, start: usize) -> bool {
+        self.regions.iter().any(|region| {
+            region.off.start == start || region.on.is_some_and(|on| on.start == start)
+        })
+    }
+
+    /// Leading のコメントを含めた要素の開始位置
+    pub fn start(&self, cst: &Cst) -> usize {
+        self.get(cst, Placement::Leading)
+            .iter()
+            .map(|comment| comment.span.start)
+            .fold(cst.span.start, usize::min)
+    }
+
+    /// Trailing のコメントを含めた要素の終了位置
+    pub fn end(&self, cst: &Cst) -> usize {
+        self.get(cst, Placement::Trailing)
+            .iter()
+            .map(|comment| comment.span.end)
+            .fold(cst.span.end, usize::max)
     }
 }
 
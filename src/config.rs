@@ -0,0 +1,148 @@
+use crate::error::ConfigError;
+use crate::options::{CommandSpace, FormatOptions};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// 設定ファイルの名前 (前にあるものを優先する)
+pub const CONFIG_FILE_NAMES: [&str; 2] = ["satysfi-fmt.toml", ".satysfi-fmt.toml"];
+
+/// 設定ファイル `satysfi-fmt.toml` の内容
+/// 指定されていない項目は None になり、後から merge したもので上書きできる
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// インデントの幅
+    pub indent_space: Option<usize>,
+    /// スペースの代わりにタブでインデントする
+    pub use_tabs: Option<bool>,
+    /// タブの表示幅
+    pub tab_width: Option<usize>,
+    /// 1行の最大幅
+    pub max_width: Option<usize>,
+    /// 末尾に改行を入れるかどうか
+    pub insert_final_newline: Option<bool>,
+    /// 連続する空行をいくつまで残すか
+    pub max_blank_lines: Option<usize>,
+    /// 空白を除いた長さがこれ以上の list は max_width に収まっても改行する (指定しなければ使わない)
+    pub list_break_width: Option<usize>,
+    /// 要素数がこれより多い record は max_width に収まっても改行する (指定しなければ使わない)
+    pub record_break_fields: Option<usize>,
+    /// コマンド名と引数の間に空白を入れるかどうか (`auto`, `always`, `never`)
+    pub command_space: Option<CommandSpace>,
+    /// 行末のコメントの列を揃えるかどうか
+    pub align_trailing_comments: Option<bool>,
+    /// 引数を元の文字列のまま出力するコマンド
+    pub verbatim_commands: Option<Vec<String>>,
+}
+
+impl Config {
+    /// 何も設定されていないときの値
+    pub fn defaults() -> Self {
+        Self::from(FormatOptions::default())
+    }
+
+    /// 設定ファイルを読み込む
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_owned(), err))?;
+        toml::from_str(&text).map_err(|err| ConfigError::Parse(path.to_owned(), err))
+    }
+
+    /// 設定ファイルを探す
+    /// `dir` から親ディレクトリに向かって探し、見つからなければユーザーの設定ディレクトリ
+    /// (`~/.config/satysfi-fmt/`) とホームディレクトリを探す
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        let user_dirs = [
+            dirs::config_dir().map(|dir| dir.join("satysfi-fmt")),
+            dirs::home_dir(),
+        ];
+        dir.ancestors()
+            .map(Path::to_path_buf)
+            .chain(user_dirs.into_iter().flatten())
+            .flat_map(|dir| CONFIG_FILE_NAMES.map(|name| dir.join(name)))
+            .find(|path| path.is_file())
+    }
+
+    /// `dir` に対応する設定ファイルを探して読み込む
+    /// 見つからなかった場合は空の設定を返す
+    pub fn discover(dir: &Path) -> Result<(Self, Option<PathBuf>), ConfigError> {
+        match Self::find(dir) {
+            Some(path) => Ok((Self::load(&path)?, Some(path))),
+            None => Ok((Self::default(), None)),
+        }
+    }
+
+    /// `other` で設定されている項目を上書きする
+    pub fn merge(self, other: Config) -> Self {
+        Self {
+            indent_space: other.indent_space.or(self.indent_space),
+            use_tabs: other.use_tabs.or(self.use_tabs),
+            tab_width: other.tab_width.or(self.tab_width),
+            max_width: other.max_width.or(self.max_width),
+            insert_final_newline: other.insert_final_newline.or(self.insert_final_newline),
+            max_blank_lines: other.max_blank_lines.or(self.max_blank_lines),
+            list_break_width: other.list_break_width.or(self.list_break_width),
+            record_break_fields: other.record_break_fields.or(self.record_break_fields),
+            command_space: other.command_space.or(self.command_space),
+            align_trailing_comments: other
+                .align_trailing_comments
+                .or(self.align_trailing_comments),
+            verbatim_commands: other.verbatim_commands.or(self.verbatim_commands),
+        }
+    }
+
+    /// formatter に渡す option に変換する
+    /// 設定されていない項目は default の値を使う
+    pub fn format_options(&self) -> FormatOptions {
+        let default = FormatOptions::default();
+        FormatOptions {
+            indent_width: self.indent_space.unwrap_or(default.indent_width),
+            use_tabs: self.use_tabs.unwrap_or(default.use_tabs),
+            tab_width: self.tab_width.unwrap_or(default.tab_width),
+            max_width: self.max_width.unwrap_or(default.max_width),
+            insert_final_newline: self
+                .insert_final_newline
+                .unwrap_or(default.insert_final_newline),
+            max_blank_lines: self.max_blank_lines.unwrap_or(default.max_blank_lines),
+            list_break_width: self.list_break_width.or(default.list_break_width),
+            record_break_fields: self.record_break_fields.or(default.record_break_fields),
+            command_space: self.command_space.unwrap_or(default.command_space),
+            align_trailing_comments: self
+                .align_trailing_comments
+                .unwrap_or(default.align_trailing_comments),
+            verbatim_commands: self
+                .verbatim_commands
+                .clone()
+                .unwrap_or(default.verbatim_commands),
+        }
+    }
+}
+
+impl From<FormatOptions> for Config {
+    fn from(option: FormatOptions) -> Self {
+        Self {
+            indent_space: Some(option.indent_width),
+            use_tabs: Some(option.use_tabs),
+            tab_width: Some(option.tab_width),
+            max_width: Some(option.max_width),
+            insert_final_newline: Some(option.insert_final_newline),
+            max_blank_lines: Some(option.max_blank_lines),
+            list_break_width: option.list_break_width,
+            record_break_fields: option.record_break_fields,
+            command_space: Some(option.command_space),
+            align_trailing_comments: Some(option.align_trailing_comments),
+            verbatim_commands: Some(option.verbatim_commands),
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::PositionEncoding;
+use lspower::lsp::TextEdit;
+use similar::{DiffTag, TextDiff};
+
+/// format 前後の文字列の unified diff を返す
+/// 差分がない場合は空文字列を返す
+/// * `original` - format 前の文字列
+/// * `formatted` - format 後の文字列
+/// * `path` - diff のヘッダに表示するファイル名
+pub fn unified_diff(original: &str, formatted: &str, path: &str) -> String {
+    if original == formatted {
+        return String::new();
+    }
+    TextDiff::from_lines(original, formatted)
+        .unified_diff()
+        .context_radius(3)
+        .header(path, path)
+        .to_string()
+}
+
+/// format 前後の差分を、変更のあった箇所ごとの TextEdit にする
+/// 行単位の差分をとったあと、前後の共通部分を取り除いて編集範囲を小さくする
+/// 位置は `encoding` の単位で数える
+pub(crate) fn text_edits(
+    original: &str,
+    formatted: &str,
+    encoding: PositionEncoding,
+) -> Vec<TextEdit> {
+    let diff = TextDiff::from_lines(original, formatted);
+    let old_offsets = line_offsets(diff.old_slices());
+    let new_offsets = line_offsets(diff.new_slices());
+
+    // 隣接する削除・挿入は1つの hunk にまとめる
+    let mut hunks: Vec<(std::ops::Range<usize>, std::ops::Range<usize>)> = Vec::new();
+    for op in diff.ops() {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        match hunks.last_mut() {
+            Some((last_old, last_new))
+                if last_old.end == old.start && last_new.end == new.start =>
+            {
+                last_old.end = old.end;
+                last_new.end = new.end;
+            }
+            _ => hunks.push((old, new)),
+        }
+    }
+
+    hunks
+        .into_iter()
+        .map(|(old, new)| {
+            let start = old_offsets[old.start];
+            let end = old_offsets[old.end];
+            let old_text = &original[start..end];
+            let new_text = &formatted[new_offsets[new.start]..new_offsets[new.end]];
+
+            let prefix = common_prefix(old_text, new_text);
+            let suffix = common_suffix(&old_text[prefix..], &new_text[prefix..]);
+            TextEdit {
+                range: encoding.range(original, start + prefix..end - suffix),
+                new_text: new_text[prefix..new_text.len() - suffix].to_string(),
+            }
+        })
+        .collect()
+}
+
+/// 各行の先頭の byte offset (末尾に全体の長さを含む)
+fn line_offsets(lines: &[&str]) -> Vec<usize> {
+    let mut offsets = vec![0];
+    for line in lines {
+        offsets.push(offsets.last().unwrap() + line.len());
+    }
+    offsets
+}
+
+/// 共通する先頭部分の byte 数
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars()
+        .zip(b.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum()
+}
+
+/// 共通する末尾部分の byte 数
+fn common_suffix(a: &str, b: &str) -> usize {
+    a.chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum()
+}
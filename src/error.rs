@@ -0,0 +1,121 @@
+use crate::PositionEncoding;
+use lspower::lsp::{Diagnostic, DiagnosticSeverity};
+use std::{fmt, io, path::PathBuf};
+
+/// format に失敗したときのエラー
+/// satysfi-parser が parse に失敗した位置と、期待していた規則を保持する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    /// エラーの行 (1-indexed)
+    pub line: usize,
+    /// エラーの列 (1-indexed, 文字単位)
+    pub column: usize,
+    /// parser が期待していた規則・トークン
+    pub expected: Vec<String>,
+    /// エラー位置を含む行の元の文字列
+    pub snippet: String,
+}
+
+impl FormatError {
+    pub(crate) fn new(input: &str, line: usize, column: usize, expected: Vec<String>) -> Self {
+        let snippet = input
+            .lines()
+            .nth(line.saturating_sub(1))
+            .unwrap_or_default()
+            .to_string();
+        Self {
+            line,
+            column,
+            expected,
+            snippet,
+        }
+    }
+}
+
+impl FormatError {
+    /// エラー位置の `input` での byte offset
+    fn offset(&self, input: &str) -> usize {
+        let line_start = input
+            .split_inclusive('\n')
+            .take(self.line.saturating_sub(1))
+            .map(str::len)
+            .sum::<usize>();
+        let column = input[line_start..]
+            .chars()
+            .take_while(|c| *c != '\n')
+            .take(self.column.saturating_sub(1))
+            .map(char::len_utf8)
+            .sum::<usize>();
+        line_start + column
+    }
+
+    /// LSP の Diagnostic に変換する
+    /// エラー位置の1文字を範囲とし、位置は `encoding` の単位で数える
+    pub fn to_diagnostic(&self, input: &str, encoding: PositionEncoding) -> Diagnostic {
+        let start = self.offset(input);
+        let end = start
+            + input[start..]
+                .chars()
+                .next()
+                .filter(|c| *c != '\n')
+                .map_or(0, char::len_utf8);
+        let message = if self.expected.is_empty() {
+            "parse error".to_string()
+        } else {
+            format!("parse error: expected {}", self.expected.join(", "))
+        };
+        Diagnostic {
+            range: encoding.range(input, start..end),
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("satysfi-fmt".to_string()),
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "[parse error] line: {}, column: {}",
+            self.line, self.column
+        )?;
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        writeln!(f, "{number} | {}", self.snippet)?;
+        write!(
+            f,
+            "{gutter} | {}^",
+            " ".repeat(self.column.saturating_sub(1))
+        )?;
+        if !self.expected.is_empty() {
+            write!(f, "\nexpected: {}", self.expected.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// 設定ファイルの読み込みに失敗したときのエラー
+#[derive(Debug)]
+pub enum ConfigError {
+    /// ファイルの読み込みに失敗した
+    Io(PathBuf, io::Error),
+    /// toml として不正
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "invalid config {}: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
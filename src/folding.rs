@@ -0,0 +1,104 @@
+use crate::comment::get_comments;
+use lspower::lsp::{FoldingRange, FoldingRangeKind};
+use satysfi_parser::{Cst, CstText, Rule};
+
+/// 閉じ括弧や `end` で終わる、折りたたみの対象になる規則
+const BLOCK_RULES: [Rule; 6] = [
+    Rule::block_text,
+    Rule::cmd_text_arg,
+    Rule::module_stmt,
+    Rule::record,
+    Rule::type_record,
+    Rule::list,
+];
+
+/// cst の span から折りたたみ範囲を作る
+pub struct FoldingCollector<'a> {
+    text: &'a str,
+    /// 改行文字の byte offset
+    newlines: Vec<usize>,
+}
+
+impl<'a> FoldingCollector<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let newlines = text.match_indices('\n').map(|(index, _)| index).collect();
+        Self { text, newlines }
+    }
+
+    /// ブロックと連続するコメント行の折りたたみ範囲を行の順に返す
+    pub fn collect(&self, csttext: &CstText) -> Vec<FoldingRange> {
+        let mut ranges = Vec::new();
+        self.blocks(&csttext.cst, &mut ranges);
+        ranges.extend(self.comments(csttext));
+        ranges.sort_by_key(|range| (range.start_line, range.end_line));
+        ranges
+    }
+
+    /// 複数行にわたるブロックを再帰的に探す
+    fn blocks(&self, cst: &Cst, ranges: &mut Vec<FoldingRange>) {
+        let self_text = &self.text[cst.span.start..cst.span.end];
+        let end = cst.span.start + self_text.trim_end().len();
+        let start_line = self.line(cst.span.start);
+        let end_line = self.line(end);
+        let range = if BLOCK_RULES.contains(&cst.rule) {
+            // 閉じ括弧や `end` の行は表示したままにする
+            Some((start_line, end_line.saturating_sub(1)))
+        } else if cst.rule == Rule::match_arm {
+            Some((start_line, end_line))
+        } else {
+            None
+        };
+        if let Some((start_line, end_line)) = range {
+            if start_line < end_line {
+                ranges.push(FoldingRange {
+                    start_line,
+                    start_character: None,
+                    end_line,
+                    end_character: None,
+                    kind: None,
+                });
+            }
+        }
+        for inner in cst.inner.iter() {
+            self.blocks(inner, ranges);
+        }
+    }
+
+    /// `%` で始まる行が2行以上続く部分を1つの折りたたみ範囲にする
+    fn comments(&self, csttext: &CstText) -> Vec<FoldingRange> {
+        let lines = get_comments(csttext)
+            .into_iter()
+            .filter(|comment| {
+                // 行末のコメントは対象にしない
+                let line_start = self.text[..comment.span.start]
+                    .rfind('\n')
+                    .map_or(0, |index| index + 1);
+                self.text[line_start..comment.span.start].trim().is_empty()
+            })
+            .map(|comment| self.line(comment.span.start))
+            .collect::<Vec<_>>();
+
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for line in lines {
+            match runs.last_mut() {
+                Some((_, end)) if *end + 1 == line => *end = line,
+                _ => runs.push((line, line)),
+            }
+        }
+        runs.into_iter()
+            .filter(|(start, end)| start < end)
+            .map(|(start_line, end_line)| FoldingRange {
+                start_line,
+                start_character: None,
+                end_line,
+                end_character: None,
+                kind: Some(FoldingRangeKind::Comment),
+            })
+            .collect()
+    }
+
+    /// byte offset の行番号 (0-indexed)
+    fn line(&self, offset: usize) -> u32 {
+        self.newlines.partition_point(|newline| *newline < offset) as u32
+    }
+}
@@ -1,25 +1,115 @@
-use crate::comment::{get_comments, to_comment_string, Comment};
+use crate::comment::{to_comment_string, Comment, Placement, Trivia};
+use crate::helper::{indent_space, indent_tab};
+use crate::options::{CommandSpace, FormatOptions};
 use crate::reserved_words::*;
-use lspower::lsp::FormattingOptions;
-use satysfi_parser::{Cst, CstText};
-use std::collections::VecDeque;
+use satysfi_parser::{Cst, CstText, Rule};
+use std::{cell::Cell, fmt};
+use unicode_width::UnicodeWidthStr;
+
+/// 閉じ括弧などを改行してから出力する規則
+/// 中身の最後に Dangling のコメントがあっても、コメントの後ろに改行を入れなくてよい
+const BREAK_LINE_RULES: [Rule; 12] = [
+    Rule::vertical,
+    Rule::block_text,
+    Rule::cmd_text_arg,
+    Rule::horizontal_text,
+    Rule::horizontal_single,
+    Rule::record,
+    Rule::type_record,
+    Rule::list,
+    Rule::sig_stmt,
+    Rule::struct_stmt,
+    Rule::preamble,
+    Rule::program_saty,
+];
+
+/// 出力に一時的に入れる印
+/// 元の文字列に含まれない文字 (`sentinel`) で挟んで埋め込み、出力の最後に取り除く
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    /// 行末のコメントの前
+    /// 空白に置き換える (`align_trailing_comments` の場合は列を揃える)
+    Trailing,
+    /// `% satysfi-fmt: off` と `% satysfi-fmt: on` のコメントの前
+    /// 元の文字列でのコメントの開始位置を持ち、off から on までを元の文字列に置き換える
+    Bound(usize),
+    /// 元の文字列のまま出力する要素
+    /// 元の文字列での span を持ち、行末の空白の除去などが終わった後に元の文字列に戻す
+    Verbatim(usize, usize),
+}
+
+impl Mark {
+    /// sentinel で挟まれた部分を印として読む
+    /// 印の形式になっていない場合は None を返す
+    fn parse(body: &str) -> Option<Self> {
+        if body == "T" {
+            return Some(Mark::Trailing);
+        }
+        if let Some(start) = body.strip_prefix('B') {
+            return start.parse().ok().map(Mark::Bound);
+        }
+        let (start, end) = body.strip_prefix('V')?.split_once(':')?;
+        Some(Mark::Verbatim(start.parse().ok()?, end.parse().ok()?))
+    }
+}
+
+impl fmt::Display for Mark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mark::Trailing => write!(f, "T"),
+            Mark::Bound(start) => write!(f, "B{start}"),
+            Mark::Verbatim(start, end) => write!(f, "V{start}:{end}"),
+        }
+    }
+}
+
+/// 出力を印とそれ以外の文字列に分けたもの
+#[derive(Debug, Clone, Copy)]
+enum Segment<'s> {
+    Text(&'s str),
+    Mark(Mark),
+}
+
+/// 印を挟む文字として、元の文字列に含まれない私用領域の文字を選ぶ
+/// 全て含まれている場合は置換文字を使う (印として読めない部分は文字列のまま扱うので panic はしない)
+fn sentinel(text: &str) -> char {
+    ('\u{E000}'..='\u{F8FF}')
+        .chain('\u{F0000}'..='\u{FFFFD}')
+        .find(|ch| !text.contains(*ch))
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// 子要素の文字列、または子要素に付いたコメントの文字列
+enum Element {
+    Node(Rule, String),
+    /// 子要素の前のコメント (末尾に改行を含む)
+    Comment(String),
+    /// 子要素と同じ行の末尾のコメント (前の空白を含む)
+    Trailing(String),
+}
 
 pub struct Formatter<'a> {
     pub text: &'a str,
     pub lines: &'a Vec<usize>,
-    pub comments: VecDeque<Comment>,
+    trivia: Trivia,
+    /// 出力で行頭になる位置 (元の文字列での位置)
+    /// 要素を改行してから出力し直すとき、元の文字列で同じ行にある前の部分を行の幅に数えないようにする
+    line_head: Cell<usize>,
+    /// 出力に埋め込む印を挟む文字
+    sentinel: char,
     pub depth: usize,
     pub output: String,
-    option: FormattingOptions,
+    option: FormatOptions,
 }
 
 impl<'a> Formatter<'a> {
-    pub fn new(csttext: &'a CstText, option: FormattingOptions) -> Self {
-        let comments = get_comments(csttext);
+    pub fn new(csttext: &'a CstText, option: FormatOptions) -> Self {
         Self {
             text: &csttext.text,
             lines: &csttext.lines,
-            comments,
+            trivia: Trivia::new(csttext),
+            line_head: Cell::new(0),
+            sentinel: sentinel(&csttext.text),
             depth: 0,
             output: String::new(),
             option,
@@ -29,21 +119,223 @@ impl<'a> Formatter<'a> {
     /// 文字列を format して出力する
     /// 前処理後処理もここで行う
     pub fn format(&self, input: &str, cst: &Cst, depth: usize) -> String {
-        let mut output = self.to_string_cst(input, cst, depth);
-        // 末尾スペースを全て除去
-        output = output
-            .split('\n')
-            .map(|line| line.trim_end())
-            .collect::<Vec<_>>()
-            .join("\n");
+        let output = self.to_string_cst(input, cst, depth);
+        let output = trim_line_end(&self.align_trailing_comments(input, &output));
+        let output = self.restore_regions(input, &output, input.len());
+        let mut output = self.restore_verbatim(input, &output);
 
         // 末尾に改行がない場合、改行を挿入して終了
-        if self.option.insert_final_newline.unwrap_or(true) && !output.ends_with('\n') {
+        if self.option.insert_final_newline && !output.ends_with('\n') {
             output += "\n";
         }
         output
     }
 
+    /// 文書の一部の cst だけを depth の深さで format する
+    /// 元の文字列に埋め込むため、前後の空白は除去し末尾の改行も入れない
+    /// cst の前後に付いたコメントは元の文字列に残っているので出力しない
+    pub fn format_cst(&self, input: &str, cst: &Cst, depth: usize) -> String {
+        let output = self.to_string_node(input, cst, depth);
+        let output = trim_line_end(&self.align_trailing_comments(input, &output));
+        let end = cst.span.start + input[cst.span.start..cst.span.end].trim_end().len();
+        let output = self.restore_regions(input, &output, end);
+        self.restore_verbatim(input, &output).trim().to_string()
+    }
+
+    /// 出力に埋め込む印の文字列
+    fn mark(&self, mark: Mark) -> String {
+        format!("{0}{mark}{0}", self.sentinel)
+    }
+
+    /// 出力を印とそれ以外の文字列に分ける
+    /// 印として読めない部分は文字列として扱う
+    fn segments<'s>(&self, output: &'s str) -> Vec<Segment<'s>> {
+        let mut segments = Vec::new();
+        let mut rest = output;
+        while let Some(index) = rest.find(self.sentinel) {
+            let body_start = index + self.sentinel.len_utf8();
+            let mark = rest[body_start..]
+                .split_once(self.sentinel)
+                .and_then(|(body, after)| Mark::parse(body).map(|mark| (mark, after)));
+            match mark {
+                Some((mark, after)) => {
+                    segments.push(Segment::Text(&rest[..index]));
+                    segments.push(Segment::Mark(mark));
+                    rest = after;
+                }
+                None => {
+                    segments.push(Segment::Text(&rest[..body_start]));
+                    rest = &rest[body_start..];
+                }
+            }
+        }
+        segments.push(Segment::Text(rest));
+        segments
+    }
+
+    /// `verbatim` の印を元の文字列に戻す
+    /// 他の印は出力の最後と同じく、行末のコメントの印は空白に、整形しない範囲の端の印は取り除く
+    fn restore_verbatim(&self, input: &str, output: &str) -> String {
+        self.segments(output)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Text(s) => s,
+                Segment::Mark(Mark::Trailing) => " ",
+                Segment::Mark(Mark::Bound(_)) => "",
+                Segment::Mark(Mark::Verbatim(start, end)) => input.get(start..end).unwrap_or(""),
+            })
+            .collect()
+    }
+
+    /// 印を元に戻したときの最後の行の表示幅
+    fn last_line_width(&self, input: &str, s: &str) -> usize {
+        let s = self.restore_verbatim(input, s);
+        s.rsplit('\n').next().unwrap_or("").width()
+    }
+
+    /// `% satysfi-fmt: off` から `% satysfi-fmt: on` までの出力を元の文字列に置き換える
+    /// on がない (出力に含まれない) 場合は、元の文字列の `end` までに置き換える
+    fn restore_regions(&self, input: &str, output: &str, end: usize) -> String {
+        let segments = self.segments(output);
+        let mut restored = String::new();
+        let mut index = 0;
+        while index < segments.len() {
+            let start = match segments[index] {
+                Segment::Text(s) => {
+                    restored += s;
+                    index += 1;
+                    continue;
+                }
+                Segment::Mark(Mark::Bound(start)) => start,
+                Segment::Mark(mark) => {
+                    restored += &self.mark(mark);
+                    index += 1;
+                    continue;
+                }
+            };
+            index += 1;
+            let region = match self.trivia.region(start) {
+                Some(region) => region,
+                // 対応する off が出力されていない on の印は取り除くだけ
+                None => continue,
+            };
+            let on = region.on.and_then(|on| {
+                segments[index..]
+                    .iter()
+                    .position(|segment| {
+                        matches!(segment, Segment::Mark(Mark::Bound(start)) if *start == on.start)
+                    })
+                    .map(|position| (on, index + position))
+            });
+            match on {
+                Some((on, on_index)) => {
+                    restored += &input[region.off.start..on.end];
+                    // on のコメントの行末まで読み飛ばす
+                    index = on_index + 1;
+                    while let Some(segment) = segments.get(index) {
+                        index += 1;
+                        if let Segment::Text(s) = segment {
+                            if let Some(line_end) = s.find('\n') {
+                                restored += &s[line_end..];
+                                break;
+                            }
+                        }
+                    }
+                }
+                None => {
+                    restored += input[region.off.start..end].trim_end();
+                    break;
+                }
+            }
+        }
+        restored
+    }
+
+    /// 行末のコメントの前の印を空白に置き換える
+    /// `align_trailing_comments` の場合は、行末のコメントがある行が続く部分でコメントの列を揃える
+    /// 列は全角文字を2と数える表示幅で揃え、元の文字列のまま出力する要素は元の文字列の幅で数える
+    fn align_trailing_comments(&self, input: &str, output: &str) -> String {
+        let mark = self.mark(Mark::Trailing);
+        if !self.option.align_trailing_comments {
+            return output.replace(&mark, " ");
+        }
+        let lines = output.split('\n').collect::<Vec<_>>();
+        let mut aligned = Vec::new();
+        let mut index = 0;
+        while index < lines.len() {
+            let count = lines[index..]
+                .iter()
+                .take_while(|line| line.contains(&mark))
+                .count();
+            if count == 0 {
+                aligned.push(lines[index].to_string());
+                index += 1;
+                continue;
+            }
+            let group = &lines[index..index + count];
+            let column = group
+                .iter()
+                .filter_map(|line| line.split_once(&mark))
+                .map(|(code, _)| self.last_line_width(input, code))
+                .max()
+                .unwrap_or_default();
+            for line in group {
+                let (code, comment) = line.split_once(&mark).unwrap_or((line, ""));
+                let padding = " ".repeat(column - self.last_line_width(input, code) + 1);
+                aligned.push(code.to_string() + &padding + comment);
+            }
+            index += count;
+        }
+        aligned.join("\n")
+    }
+
+    /// depth の深さのインデントを返す
+    fn indent(&self, depth: usize) -> String {
+        if self.option.use_tabs {
+            indent_tab(depth)
+        } else {
+            indent_space(self.option.indent_width * depth)
+        }
+    }
+
+    /// depth の深さのインデントの表示幅
+    fn indent_width(&self, depth: usize) -> usize {
+        if self.option.use_tabs {
+            self.option.tab_width * depth
+        } else {
+            self.option.indent_width * depth
+        }
+    }
+
+    /// cst を `s` として出力したとき、インデントと同じ行の前の部分を含めて max_width に収まる1行になるか
+    /// 親要素の出力はまだ決まっていないので、前の部分は元の文字列で同じ行にある部分 (連続する空白は1つにする) で数える
+    fn fits(&self, cst: &Cst, depth: usize, s: &str) -> bool {
+        // 元の文字列のまま出力する要素は、元の文字列の幅で数える
+        let s = self.restore_verbatim(self.text, s);
+        if s.contains('\n') {
+            return false;
+        }
+        let line_start = self.text[..cst.span.start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1)
+            .max(self.line_head.get().min(cst.span.start));
+        let before = &self.text[line_start..cst.span.start];
+        let mut prefix = before.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !prefix.is_empty() && before.ends_with(char::is_whitespace) {
+            prefix.push(' ');
+        }
+        self.indent_width(depth) + prefix.width() + s.width() <= self.option.max_width
+    }
+
+    /// 改行してから出力するときの cst の文字列
+    /// 元の文字列で同じ行にある前の部分を、max_width に収まるかの判定に含めない
+    fn to_string_cst_on_new_line(&self, text: &str, cst: &Cst, depth: usize) -> String {
+        let line_head = self.line_head.replace(cst.span.start);
+        let output = self.to_string_cst(text, cst, depth);
+        self.line_head.set(line_head);
+        output
+    }
+
     /// cst の inner の要素を結合して文字列に変換する関数
     fn to_string_cst_inner(&self, text: &str, cst: &Cst, depth: usize) -> String {
         /*
@@ -53,13 +345,15 @@ impl<'a> Formatter<'a> {
             inner: [Cst]
         }
         */
-        use satysfi_parser::Rule;
         let csts = cst.inner.clone();
         // 関数内で改行するときはこれを使用する
-        let indent = indent_space(self.option.tab_size as usize, depth);
+        let indent = self.indent(depth);
         let newline = format!("\n{indent}");
         let sep = &match cst.rule {
-            Rule::block_cmd | Rule::inline_cmd => " ".to_string(),
+            Rule::block_cmd | Rule::inline_cmd => match self.option.command_space {
+                CommandSpace::Never => "".to_string(),
+                CommandSpace::Auto | CommandSpace::Always => " ".to_string(),
+            },
             // Rule::type_application => " ".to_string(),
             Rule::type_prod => " * ".to_string(),
             Rule::dyadic_expr | Rule::match_expr | Rule::unary_operator_expr => " ".to_string(),
@@ -67,7 +361,6 @@ impl<'a> Formatter<'a> {
             Rule::horizontal_list => format!("{newline}|"),
             Rule::unary => "#".to_string(),
             Rule::type_optional => " ?-> ".to_string(),
-            Rule::list => format!(";{newline}"),
             Rule::tuple => ", ".to_string(),
             Rule::record | Rule::type_record => newline.clone(),
             Rule::type_block_cmd | Rule::type_inline_cmd | Rule::type_math_cmd => {
@@ -106,17 +399,12 @@ impl<'a> Formatter<'a> {
                         Rule::expr => {
                             if s.contains('\n') {
                                 // 1つインデントを深くする
-                                let s = self.to_string_cst(text, now_cst, depth + 1);
-                                current
-                                    + " <-"
-                                    + &newline
-                                    + &indent_space(self.option.tab_size as usize, 1)
-                                    + s.trim_start()
+                                let s = self.to_string_cst_on_new_line(text, now_cst, depth + 1);
+                                current + " <-" + &newline + &self.indent(1) + s.trim_start()
                             } else {
                                 current + " <- " + &s
                             }
                         }
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 })
@@ -126,9 +414,7 @@ impl<'a> Formatter<'a> {
                 let output = csts.iter().fold(String::new(), |current, now_cst| {
                     let s = self.to_string_cst(text, now_cst, depth);
                     if current.is_empty() {
-                        if now_cst.rule != Rule::comments {
-                            cnt += 1;
-                        }
+                        cnt += 1;
                         return s;
                     }
                     match now_cst.rule {
@@ -148,7 +434,6 @@ impl<'a> Formatter<'a> {
                                 current + &s
                             }
                         }
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 });
@@ -179,13 +464,6 @@ impl<'a> Formatter<'a> {
                         Rule::inline_cmd_name => current + " " + &s,
                         Rule::block_cmd_name => current + " " + &s,
                         Rule::type_expr => current + ": " + &s,
-                        Rule::comments => {
-                            if current.ends_with(char::is_whitespace) {
-                                current + &s
-                            } else {
-                                current + &newline + &s
-                            }
-                        }
                         _ => current + " " + &s,
                     }
                 })
@@ -196,72 +474,48 @@ impl<'a> Formatter<'a> {
             | Rule::let_inline_stmt_noctx
             | Rule::let_stmt
             | Rule::let_math_stmt => {
-                csts.iter()
-                    .enumerate()
-                    .fold(String::new(), |current, (index, now_cst)| {
-                        let s = self.to_string_cst(text, now_cst, depth);
-                        let s = if cst.rule == Rule::sig_val_stmt
-                            && now_cst.rule == Rule::bin_operator
-                        {
-                            format!("({s})")
-                        } else {
-                            s
-                        };
-                        if current.is_empty() {
-                            return s;
+                csts.iter().fold(String::new(), |current, now_cst| {
+                    let s = self.to_string_cst(text, now_cst, depth);
+                    let s = if cst.rule == Rule::sig_val_stmt && now_cst.rule == Rule::bin_operator
+                    {
+                        format!("({s})")
+                    } else {
+                        s
+                    };
+                    if current.is_empty() {
+                        return s;
+                    }
+                    match now_cst.rule {
+                        Rule::var => current + " " + &s,
+                        Rule::block_cmd_name => current + " " + &s,
+                        Rule::bin_operator => current + &format!(" ({s})"),
+                        Rule::type_expr => current + ": " + &s,
+                        Rule::constraint => {
+                            // 1つインデントを深くする
+                            let s = self.to_string_cst(text, now_cst, depth + 1);
+                            current + &newline + &self.indent(1) + &s
                         }
-                        match now_cst.rule {
-                            Rule::var => current + " " + &s,
-                            Rule::block_cmd_name => current + " " + &s,
-                            Rule::bin_operator => current + &format!(" ({s})"),
-                            Rule::type_expr => current + ": " + &s,
-                            Rule::constraint => {
+                        Rule::expr => {
+                            // 直前にコメントがある
+                            if !self.trivia.get(now_cst, Placement::Leading).is_empty() {
                                 // 1つインデントを深くする
-                                let s = self.to_string_cst(text, now_cst, depth + 1);
-                                current
-                                    + &newline
-                                    + &indent_space(self.option.tab_size as usize, 1)
-                                    + &s
-                            }
-                            Rule::expr => {
-                                // 直前がコメント
-                                if index > 0 && csts[index - 1].rule == Rule::comments {
-                                    // 1つインデントを深くする
-                                    let s = self.to_string_cst(text, now_cst, depth + 1);
-                                    current + &s
-                                }
-                                // ブロック定義は例外
-                                else if s.starts_with("let")
-                                    || (!s.starts_with("'<") && !s.starts_with('{'))
-                                        && s.contains('\n')
-                                {
-                                    // 1つインデントを深くする
-                                    let s = self.to_string_cst(text, now_cst, depth + 1);
-                                    current
-                                        + " ="
-                                        + &newline
-                                        + &indent_space(self.option.tab_size as usize, 1)
-                                        + s.trim_start()
-                                } else {
-                                    current + " = " + &s
-                                }
+                                let s = self.to_string_cst_on_new_line(text, now_cst, depth + 1);
+                                current + " =" + &newline + &self.indent(1) + &s
                             }
-                            Rule::comments => {
-                                if index + 1 < csts.len() && csts[index + 1].rule == Rule::expr {
-                                    // 1つインデントを深くする
-                                    let s = self.to_string_cst(text, now_cst, depth + 1);
-                                    current
-                                        + " ="
-                                        + &newline
-                                        + &indent_space(self.option.tab_size as usize, 1)
-                                        + &s
-                                } else {
-                                    current + &s
-                                }
+                            // ブロック定義は例外
+                            else if s.starts_with("let")
+                                || (!s.starts_with("'<") && !s.starts_with('{')) && s.contains('\n')
+                            {
+                                // 1つインデントを深くする
+                                let s = self.to_string_cst_on_new_line(text, now_cst, depth + 1);
+                                current + " =" + &newline + &self.indent(1) + s.trim_start()
+                            } else {
+                                current + " = " + &s
                             }
-                            _ => current + " " + &s,
                         }
-                    })
+                        _ => current + " " + &s,
+                    }
+                })
             }
             Rule::math_cmd_expr_arg | Rule::math_cmd_expr_option => {
                 // 高々1つの要素
@@ -275,7 +529,6 @@ impl<'a> Formatter<'a> {
                         Rule::vertical => current + &format!("!{s}"),
                         Rule::expr => current + &format!("!({s})"),
                         Rule::record | Rule::list => current + &format!("!{s}"),
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 })
@@ -310,7 +563,6 @@ impl<'a> Formatter<'a> {
                     }
                     Rule::pat_variant => current + " " + &s,
                     Rule::pat_as => current + " :: " + &s,
-                    Rule::comments => current + &newline + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -328,7 +580,6 @@ impl<'a> Formatter<'a> {
                         }
                     }
                     Rule::variant_name => current + " " + &s,
-                    Rule::comments => current + &newline + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -340,7 +591,6 @@ impl<'a> Formatter<'a> {
                 match now_cst.rule {
                     Rule::type_param => current + " " + &s,
                     Rule::type_record => current + " :: " + &s,
-                    Rule::comments => current + &newline + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -348,51 +598,33 @@ impl<'a> Formatter<'a> {
                 if csts.len() == 1 {
                     return self.to_string_cst(text, &csts[0], depth);
                 }
-                let mut iter = csts.into_iter().peekable();
+                let mut iter = csts.iter().peekable();
                 let mut output = String::new();
-                while iter.peek().is_some() {
-                    let now_cst = &iter.next().unwrap();
-                    let s = self.to_string_cst(text, now_cst, depth);
-                    let s = if now_cst.rule == Rule::unary {
-                        format!("{s} {} ", RESERVED_WORD.with)
-                    } else if now_cst.rule == Rule::record_unit
-                        || now_cst.rule == Rule::type_record_unit
-                    {
-                        s + ";"
-                    } else {
-                        s
-                    };
+                while let Some(now_cst) = iter.next() {
+                    // 前のコメントは別の行に、後ろのコメントは `;` の後ろに出力する
+                    for comment in self.comments(text, now_cst, Placement::Leading) {
+                        output += &comment;
+                        output += sep;
+                    }
+                    let s = self.to_string_node(text, now_cst, depth);
                     match now_cst.rule {
                         Rule::unary => {
-                            output += &s;
+                            output += &format!("{s} {} ", RESERVED_WORD.with);
                             continue;
                         }
-                        Rule::record_unit => {
-                            output += &s;
-                        }
-                        Rule::type_record_unit => {
-                            output += &s;
-                        }
-                        Rule::comments => {
+                        Rule::record_unit | Rule::type_record_unit => {
                             output += &s;
+                            output += ";";
                         }
                         _ => unreachable!(),
                     };
+                    for comment in self.trailing_comments(text, now_cst, false) {
+                        output += &comment;
+                    }
                     // 次の要素が存在すれば結合
-                    let next = iter.peek();
-                    if next.is_some()
-                        && now_cst.rule != Rule::comments
-                        && next.unwrap().rule == Rule::comments
-                    {
+                    if iter.peek().is_some() {
                         output += sep;
-                    } else if next.is_some()
-                        && (next.unwrap().rule == Rule::record_unit
-                            || next.unwrap().rule == Rule::type_record_unit)
-                    {
-                        output += sep;
-                    } else if next.is_none() && now_cst.rule == Rule::comments {
-                        output = output.trim_end().to_string();
-                    };
+                    }
                 }
                 output
             }
@@ -424,7 +656,6 @@ impl<'a> Formatter<'a> {
                     match now_cst.rule {
                         Rule::variant_name => current + &s,
                         Rule::type_expr => current + " of " + &s,
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 });
@@ -512,7 +743,6 @@ impl<'a> Formatter<'a> {
                         Rule::expr => current + "if " + &s,
                         Rule::ctrl_then => current + &newline + &s,
                         Rule::ctrl_else => current + &newline + &s,
-                        Rule::comments => current + &newline + &s,
                         _ => unreachable!(),
                     }
                 });
@@ -551,7 +781,6 @@ impl<'a> Formatter<'a> {
                                 _ => current + &s,
                             }
                         }
-                        Rule::comments => current + &s,
                         _ => current + &s,
                     }
                 });
@@ -591,7 +820,6 @@ impl<'a> Formatter<'a> {
                     }
                     match now_cst.rule {
                         Rule::pattern => current + " " + &s,
-                        Rule::comments => current + &s,
                         _ => current + " -> " + &s,
                     }
                 }),
@@ -603,7 +831,6 @@ impl<'a> Formatter<'a> {
                 match now_cst.rule {
                     Rule::var_ptn => current + " " + &s,
                     Rule::expr => current + " = " + &s,
-                    Rule::comments => current + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -615,7 +842,6 @@ impl<'a> Formatter<'a> {
                 match now_cst.rule {
                     Rule::var => current + " " + &s,
                     Rule::type_expr => current + ": " + &s,
-                    Rule::comments => current + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -623,13 +849,6 @@ impl<'a> Formatter<'a> {
                 let mut output = String::new();
                 for cst in &csts {
                     let s = self.to_string_cst(text, cst, depth);
-                    if cst.rule == Rule::comments {
-                        if !output.ends_with(char::is_whitespace) {
-                            output += &newline;
-                        }
-                        output += &s;
-                        continue;
-                    }
                     if !output.is_empty() {
                         output += sep;
                     }
@@ -653,7 +872,6 @@ impl<'a> Formatter<'a> {
                     | Rule::application
                     | Rule::unary
                     | Rule::variant_constructor => current + " <- " + &s,
-                    Rule::comments => current + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -663,15 +881,26 @@ impl<'a> Formatter<'a> {
                 }
                 let first_text = self.to_string_cst(text, &csts[0], depth);
                 let insert_space = first_text != "document";
-                let mut output = first_text;
-                for cst in csts.iter().skip(1) {
-                    let s = self.to_string_cst(text, cst, depth);
+                let args = csts
+                    .iter()
+                    .skip(1)
+                    .map(|cst| self.to_string_cst(text, cst, depth))
+                    .collect::<Vec<_>>();
+                let mut output = first_text.clone();
+                for s in args.iter() {
                     if insert_space {
                         output += " ";
                     }
-                    output += &s;
+                    output += s;
+                }
+                if insert_space && !output.contains('\n') && !self.fits(cst, depth, &output) {
+                    // 1行に収まらない場合は引数ごとに改行する
+                    let newline = format!("\n{}", self.indent(depth + 1));
+                    args.iter()
+                        .fold(first_text, |current, s| current + &newline + s)
+                } else {
+                    output
                 }
-                output
             }
             Rule::bind_stmt => {
                 // let* ~ in のとき用
@@ -689,29 +918,29 @@ impl<'a> Formatter<'a> {
                                     if index == 0 {
                                         current + &s + " " + RESERVED_WORD.in_stmt + &newline
                                     } else {
-                                        current + &newline + &s + " " + RESERVED_WORD.in_stmt + &newline
+                                        current
+                                            + &newline
+                                            + &s
+                                            + " "
+                                            + RESERVED_WORD.in_stmt
+                                            + &newline
                                     }
                                 }
                                 Rule::expr => {
                                     if s.starts_with("let") {
                                         current + s.trim_start()
                                     } else if s.contains('\n') {
-                                        let s = self.to_string_cst(text, now_cst, depth + 1);
+                                        let s = self.to_string_cst_on_new_line(
+                                            text,
+                                            now_cst,
+                                            depth + 1,
+                                        );
                                         // 1つ深くする
-                                        current
-                                            + &indent_space(self.option.tab_size as usize, 1)
-                                            + s.trim_start()
+                                        current + &self.indent(1) + s.trim_start()
                                     } else {
                                         current + s.trim_start()
                                     }
                                 }
-                                Rule::comments => {
-                                    if current.ends_with(RESERVED_WORD.in_stmt) {
-                                        current + &newline + &s
-                                    } else {
-                                        current + &s
-                                    }
-                                }
                                 _ => current + &s,
                             }
                         });
@@ -720,28 +949,38 @@ impl<'a> Formatter<'a> {
             Rule::type_expr => {
                 let mut iter = csts.into_iter().peekable();
                 let mut now_cst = iter.next().unwrap();
-                let mut output = self.to_string_cst(text, &now_cst, depth);
+                let first = self.to_string_cst(text, &now_cst, depth);
+                // (矢印, 型) の組
+                let mut types = vec![];
                 while iter.peek().is_some() {
                     // 次の要素が存在すれば結合
-                    if now_cst.rule == Rule::type_optional {
-                        output += " ?-> ";
+                    let arrow = if now_cst.rule == Rule::type_optional {
+                        "?->"
                     } else {
-                        output += " -> ";
-                    }
+                        "->"
+                    };
                     now_cst = iter.next().unwrap();
 
                     let s = self.to_string_cst(text, &now_cst, depth);
                     match now_cst.rule {
                         Rule::type_prod | Rule::type_optional => {
-                            output += &s;
-                        }
-                        Rule::comments => {
-                            output += &s;
+                            types.push((arrow, s));
                         }
                         _ => unreachable!(),
                     }
                 }
-                output
+                let output = types.iter().fold(first.clone(), |current, (arrow, s)| {
+                    current + " " + arrow + " " + s
+                });
+                if !output.contains('\n') && !self.fits(cst, depth, &output) {
+                    // 1行に収まらない場合は矢印の前で改行する
+                    let newline = format!("\n{}", self.indent(depth + 1));
+                    types.iter().fold(first, |current, (arrow, s)| {
+                        current + &newline + arrow + " " + s
+                    })
+                } else {
+                    output
+                }
             }
             Rule::module_stmt => {
                 let mut iter = csts.into_iter().peekable();
@@ -758,7 +997,6 @@ impl<'a> Formatter<'a> {
                         Rule::struct_stmt => {
                             output += " = ";
                         }
-                        Rule::comments => {}
                         _ => unreachable!(),
                     }
                     output += &s;
@@ -772,12 +1010,15 @@ impl<'a> Formatter<'a> {
                         .enumerate()
                         .fold(String::new(), |current, (index, now_cst)| {
                             let s = self.to_string_cst(text, now_cst, depth);
+                            // 直前の要素の後ろのコメントによる改行は除いてから改行を制御する
+                            let current = match current.strip_suffix(newline.as_str()) {
+                                Some(stripped) if !current.ends_with(check) => stripped.to_string(),
+                                _ => current,
+                            };
 
                             // 改行の制御
                             let current = if current.is_empty() || current.ends_with(check) {
                                 current
-                            } else if index > 0 && csts[index - 1].rule == Rule::comments {
-                                current
                             } else if index > 0 && csts[index - 1].rule != now_cst.rule {
                                 // ルールの切り替わり位置
                                 current + "\n" + &newline
@@ -792,7 +1033,6 @@ impl<'a> Formatter<'a> {
                                     Rule::let_stmt | Rule::let_rec_stmt => {
                                         current + "\n" + &newline
                                     }
-                                    Rule::comments => current,
                                     _ => {
                                         // 基本的に改行する
                                         current + &newline
@@ -801,7 +1041,6 @@ impl<'a> Formatter<'a> {
                             };
                             match now_cst.rule {
                                 Rule::let_stmt | Rule::let_rec_stmt => current + &s,
-                                Rule::comments => current + &s,
                                 Rule::bind_stmt => current + &s,
                                 _ => current + &s,
                             }
@@ -812,41 +1051,54 @@ impl<'a> Formatter<'a> {
                 let output = csts.iter().fold(String::new(), |current, now_cst| {
                     let s = self.to_string_cst(text, now_cst, depth);
                     match now_cst.rule {
-                        Rule::sig_val_stmt | Rule::sig_type_stmt => current + &newline + &s,
+                        Rule::sig_val_stmt | Rule::sig_type_stmt => {
+                            current.trim_end().to_string() + &newline + &s
+                        }
                         Rule::module_name => current + " " + &s,
                         Rule::struct_stmt => current + "= " + RESERVED_WORD.struct_stmt + &s,
-                        Rule::comments => current + &s,
                         _ => current + &s,
                     }
                 });
                 output.trim().to_string()
             }
             Rule::horizontal_single => {
-                let output = csts.iter().fold(String::new(), |current, now_cst| {
-                    let s = self.to_string_cst(text, now_cst, depth);
-                    if current.is_empty() {
-                        s
-                    } else if now_cst.rule == Rule::regular_text && s.trim().is_empty() {
-                        // 空行・スペースの処理
-                        if current.ends_with(char::is_whitespace) {
-                            // 既に空白がある場合には何もしない
-                            current
-                        } else {
-                            current + &s
-                        }
-                    } else if now_cst.rule == Rule::regular_text {
-                        if current.ends_with(char::is_whitespace) {
-                            // 既に空白がある場合には何もしない
-                            current + s.trim_start()
+                let elements = self.to_string_elements(text, &csts, depth);
+                let output = elements
+                    .into_iter()
+                    .fold(String::new(), |current, element| {
+                        let (rule, s) = match element {
+                            // コメントは別の行にする
+                            // 行末のコメントは同じ行に残す
+                            Element::Trailing(s) => {
+                                return format!("{}{s}{newline}", current.trim_end());
+                            }
+                            Element::Comment(s) if !current.is_empty() => {
+                                return format!("{}{newline}{s}", current.trim_end());
+                            }
+                            Element::Comment(s) => return s,
+                            Element::Node(rule, s) => (rule, s),
+                        };
+                        if current.is_empty() {
+                            s
+                        } else if rule == Rule::regular_text && s.trim().is_empty() {
+                            // 空行・スペースの処理
+                            if current.ends_with(char::is_whitespace) {
+                                // 既に空白がある場合には何もしない
+                                current
+                            } else {
+                                current + &s
+                            }
+                        } else if rule == Rule::regular_text {
+                            if current.ends_with(char::is_whitespace) {
+                                // 既に空白がある場合には何もしない
+                                current + s.trim_start()
+                            } else {
+                                current + &s
+                            }
                         } else {
                             current + &s
                         }
-                    } else if now_cst.rule == Rule::comments {
-                        format!("{}{newline}{s}", current.trim_end())
-                    } else {
-                        current + &s
-                    }
-                });
+                    });
 
                 // コメントが末尾にあるとき余計な改行が残ってしまうので削除
                 output.trim().to_string()
@@ -868,39 +1120,45 @@ impl<'a> Formatter<'a> {
             Rule::horizontal_list => csts.iter().fold("|".to_string(), |current, now_cst| {
                 // 実装しているが使わない
                 let s = self.to_string_cst(text, now_cst, depth);
-                let flag = now_cst.rule == Rule::comments;
-                if flag {
-                    current + &s
-                } else if s.is_empty() {
+                if s.is_empty() {
                     current
-                } else if now_cst.rule == Rule::comments {
-                    current + &s
                 } else {
                     current + " " + &s + sep
                 }
             }),
-            Rule::list => csts
-                .iter()
-                .fold(String::new(), |current, now_cst| {
-                    let s = self.to_string_cst(text, now_cst, depth);
-                    let flag = now_cst.rule == Rule::comments;
-                    if flag {
-                        current + &s
-                    } else if current.is_empty() {
-                        s + sep
-                    } else if s.is_empty() {
-                        current
-                    } else if now_cst.rule == Rule::comments {
-                        current + &s
-                    } else {
-                        current + &s + sep
+            Rule::list => {
+                let mut output = String::new();
+                for now_cst in csts.iter() {
+                    // 前のコメントは別の行に、後ろのコメントは `;` の後ろに出力する
+                    for comment in self.comments(text, now_cst, Placement::Leading) {
+                        output += &comment;
+                        output += &newline;
                     }
-                })
-                .trim_end()
-                .to_string(),
+                    let s = self.to_string_node(text, now_cst, depth);
+                    if s.is_empty() {
+                        continue;
+                    }
+                    output += &s;
+                    output += ";";
+                    for comment in self.trailing_comments(text, now_cst, false) {
+                        output += &comment;
+                    }
+                    output += &newline;
+                }
+                output.trim_end().to_string()
+            }
             Rule::block_cmd | Rule::inline_cmd => {
+                let verbatim = self.is_verbatim_command(text, cst);
                 csts.iter().fold(String::new(), |current, now_cst| {
-                    let s = self.to_string_cst(text, now_cst, depth);
+                    let s = if verbatim
+                        && !matches!(now_cst.rule, Rule::inline_cmd_name | Rule::block_cmd_name)
+                    {
+                        // 引数は元の文字列のまま出力する
+                        let node = self.verbatim(text, now_cst);
+                        self.with_comments(text, now_cst, depth, node)
+                    } else {
+                        self.to_string_cst(text, now_cst, depth)
+                    };
                     if current.is_empty() {
                         s
                     } else if s.is_empty() {
@@ -915,29 +1173,40 @@ impl<'a> Formatter<'a> {
             Rule::math_single => {
                 let mut last_token = String::new();
 
-                let output = csts.iter().fold(String::new(), |current, now_cst| {
-                    let s = self.to_string_cst(text, now_cst, depth);
+                let elements = self.to_string_elements(text, &csts, depth);
+                let output = elements
+                    .into_iter()
+                    .fold(String::new(), |current, element| {
+                        let (is_comment, s) = match element {
+                            // 行末のコメントは同じ行に残す
+                            Element::Trailing(s) => {
+                                return format!("{}{s}{newline}", current.trim_end());
+                            }
+                            Element::Comment(s) => (true, s),
+                            Element::Node(_, s) => (false, s),
+                        };
 
-                    let output = if current.is_empty() {
-                        s.clone()
-                    } else if s.is_empty() {
-                        current
-                    } else if current.ends_with(&newline) {
-                        current + &s
-                    } else if last_token.starts_with('\\') {
-                        current + sep + &s
-                    } else if now_cst.rule == Rule::comments {
-                        format!("{}{newline}{s}", current.trim_end())
-                    } else if s.starts_with(char::is_alphabetic)
-                        && current.ends_with(char::is_alphabetic)
-                    {
-                        current + &s
-                    } else {
-                        current + sep + &s
-                    };
-                    last_token = s;
-                    output
-                });
+                        let output = if current.is_empty() {
+                            s.clone()
+                        } else if s.is_empty() {
+                            current
+                        } else if current.ends_with(&newline) {
+                            current + &s
+                        } else if last_token.starts_with('\\') {
+                            current + sep + &s
+                        } else if is_comment {
+                            // コメントは別の行にする
+                            format!("{}{newline}{s}", current.trim_end())
+                        } else if s.starts_with(char::is_alphabetic)
+                            && current.ends_with(char::is_alphabetic)
+                        {
+                            current + &s
+                        } else {
+                            current + sep + &s
+                        };
+                        last_token = s;
+                        output
+                    });
                 output
             }
             Rule::math_token => csts.iter().fold(String::new(), |current, now_cst| {
@@ -965,40 +1234,62 @@ impl<'a> Formatter<'a> {
                         current
                     } else if current.ends_with(&newline) {
                         current + &s
-                    } else if s.starts_with("%\n") {
-                        current + &s
                     } else {
                         // 複数行の改行を省略して1行にする
-                        let start = now_cst.span.start;
-                        let mut cnt = 0;
+                        let start = self.trivia.start(now_cst);
+                        let mut cnt: usize = 0;
                         for &value in self.lines.iter() {
                             if line_index < value && value < start {
                                 cnt += 1;
                             }
                         }
-                        let current = if cnt > 1 { current + "\n" } else { current };
+                        // 空行は max_blank_lines まで残す
+                        let blank_lines =
+                            std::cmp::min(cnt.saturating_sub(1), self.option.max_blank_lines);
+                        let current = current + &"\n".repeat(blank_lines);
                         current + sep + &s
                     };
-                    line_index = now_cst.span.end;
+                    line_index = self.trivia.end(now_cst);
 
                     output.trim_end().to_string()
                 })
             }
-            Rule::dyadic_expr => csts.iter().fold(String::new(), |current, now_cst| {
-                let s = self.to_string_cst(text, now_cst, depth);
-                let output = if current.is_empty() {
-                    s
-                } else if s.is_empty() {
-                    current
-                } else if current.ends_with(&newline) {
-                    current + &s
-                } else if now_cst.rule == Rule::bin_operator && s.trim() == "|>" {
-                    current + &s
+            Rule::dyadic_expr => {
+                let terms = csts
+                    .iter()
+                    .map(|now_cst| (now_cst.rule, self.to_string_cst(text, now_cst, depth)))
+                    .collect::<Vec<_>>();
+                let output = terms.iter().fold(String::new(), |current, (rule, s)| {
+                    if current.is_empty() {
+                        s.clone()
+                    } else if s.is_empty() {
+                        current
+                    } else if current.ends_with(&newline) {
+                        current + s
+                    } else if *rule == Rule::bin_operator && s.trim() == "|>" {
+                        current + s
+                    } else {
+                        current + sep + s
+                    }
+                });
+                if !output.contains('\n') && !self.fits(cst, depth, &output) {
+                    // 1行に収まらない場合は演算子の前で改行する
+                    let newline = format!("\n{}", self.indent(depth + 1));
+                    terms.iter().fold(String::new(), |current, (rule, s)| {
+                        if current.is_empty() {
+                            s.clone()
+                        } else if s.is_empty() {
+                            current
+                        } else if *rule == Rule::bin_operator {
+                            current + &newline + s
+                        } else {
+                            current + sep + s
+                        }
+                    })
                 } else {
-                    current + sep + &s
-                };
-                output
-            }),
+                    output
+                }
+            }
             Rule::program_saty => {
                 csts.iter().fold(String::new(), |current, now_cst| {
                     let s = self.to_string_cst(text, now_cst, depth);
@@ -1039,8 +1330,172 @@ impl<'a> Formatter<'a> {
         output
     }
 
-    /// cst を文字列にするための関数
+    /// cst を前後に付いたコメントを含めて文字列にする関数
     fn to_string_cst(&self, text: &str, cst: &Cst, depth: usize) -> String {
+        let node = self.to_string_node(text, cst, depth);
+        self.with_comments(text, cst, depth, node)
+    }
+
+    /// cst の文字列 `node` に、前後に付いたコメントを加える
+    fn with_comments(&self, text: &str, cst: &Cst, depth: usize, node: String) -> String {
+        let newline = format!("\n{}", self.indent(depth));
+        let mut output = String::new();
+        for comment in self.comments(text, cst, Placement::Leading) {
+            output += &comment;
+            output += &newline;
+        }
+        output += &node;
+        for comment in self.trailing_comments(text, cst, false) {
+            output += &comment;
+            output += &newline;
+        }
+        output
+    }
+
+    /// cst と同じ行の末尾に付いたコメントの文字列
+    /// コメントの前の空白を含む
+    /// * `text_mode` - 地の文や数式の中のコメント。元の文字列で直前に空白がなければ詰めて出力する
+    fn trailing_comments(&self, text: &str, cst: &Cst, text_mode: bool) -> Vec<String> {
+        self.trivia
+            .get(cst, Placement::Trailing)
+            .into_iter()
+            .map(|comment| {
+                let s = self.comment_string(text, comment);
+                let spaced = text[..comment.span.start].ends_with([' ', '\t']);
+                // `%` だけのコメントは行の継続に使われ、地の文では空白の有無で意味が変わる
+                if (s == "%" || text_mode) && !spaced {
+                    s
+                } else if s == "%" {
+                    format!(" {s}")
+                } else {
+                    self.mark(Mark::Trailing) + &s
+                }
+            })
+            .collect()
+    }
+
+    /// cst を元の文字列のまま出力するための印
+    /// 出力の最後に `restore_verbatim` で元の文字列に戻す
+    fn verbatim(&self, text: &str, cst: &Cst) -> String {
+        let end = cst.span.start + text[cst.span.start..cst.span.end].trim_end().len();
+        self.mark(Mark::Verbatim(cst.span.start, end))
+    }
+
+    /// math コマンドを command_space に従って文字列にする
+    /// 引数の中身は元の文字列のまま、入れ子の math コマンドも含めてコマンド名と引数の間の空白だけを調整する
+    fn math_cmd_string(&self, text: &str, cst: &Cst) -> String {
+        let sep = match self.option.command_space {
+            CommandSpace::Always => " ",
+            CommandSpace::Auto | CommandSpace::Never => "",
+        };
+        cst.inner
+            .iter()
+            .map(|inner| match inner.rule {
+                Rule::math_cmd_name => text[inner.span.start..inner.span.end].to_string(),
+                _ => self.math_cmd_args_string(text, inner).trim().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(sep)
+    }
+
+    /// math コマンドの引数を元の文字列のまま、中の math コマンドだけを `math_cmd_string` にした文字列
+    fn math_cmd_args_string(&self, text: &str, cst: &Cst) -> String {
+        let mut output = String::new();
+        let mut index = cst.span.start;
+        for inner in cst.inner.iter() {
+            output += &text[index..inner.span.start];
+            output += &match inner.rule {
+                Rule::math_cmd => self.math_cmd_string(text, inner),
+                _ => self.math_cmd_args_string(text, inner),
+            };
+            index = inner.span.end;
+        }
+        output + &text[index..cst.span.end]
+    }
+
+    /// 引数を元の文字列のまま出力するコマンド (`verbatim_commands`) か
+    fn is_verbatim_command(&self, text: &str, cst: &Cst) -> bool {
+        cst.inner
+            .iter()
+            .find(|inner| matches!(inner.rule, Rule::inline_cmd_name | Rule::block_cmd_name))
+            .is_some_and(|name| {
+                let name = &text[name.span.start..name.span.end];
+                self.option
+                    .verbatim_commands
+                    .iter()
+                    .any(|command| command == name)
+            })
+    }
+
+    /// cst に `placement` の位置で付いたコメントの文字列
+    fn comments(&self, text: &str, cst: &Cst, placement: Placement) -> Vec<String> {
+        self.trivia
+            .get(cst, placement)
+            .into_iter()
+            .map(|comment| self.comment_string(text, comment))
+            .collect()
+    }
+
+    /// コメントの文字列
+    /// 整形しない範囲の端のコメントには、元の文字列での位置の印を付ける
+    fn comment_string(&self, text: &str, comment: &Comment) -> String {
+        let s = to_comment_string(text[comment.span.start..comment.span.end].to_string());
+        if self.trivia.is_region_bound(comment.span.start) {
+            self.mark(Mark::Bound(comment.span.start)) + &s
+        } else {
+            s
+        }
+    }
+
+    /// 子要素の文字列 (前後のコメントを含めない) と、子要素の前後に付いたコメントを元の順番に並べる
+    /// 地の文や数式のように、コメントの前後の改行を親要素で制御するときに使う
+    fn to_string_elements(&self, text: &str, csts: &[Cst], depth: usize) -> Vec<Element> {
+        let newline = format!("\n{}", self.indent(depth));
+        let mut elements = Vec::new();
+        for cst in csts {
+            for comment in self.comments(text, cst, Placement::Leading) {
+                elements.push(Element::Comment(comment + &newline));
+            }
+            elements.push(Element::Node(
+                cst.rule,
+                self.to_string_node(text, cst, depth),
+            ));
+            for comment in self.trailing_comments(text, cst, true) {
+                elements.push(Element::Trailing(comment));
+            }
+        }
+        elements
+    }
+
+    /// 子要素の後ろに Dangling のコメントを追加する
+    fn push_dangling(&self, text: &str, cst: &Cst, output: String, depth: usize) -> String {
+        let comments = self.comments(text, cst, Placement::Dangling);
+        if comments.is_empty() {
+            return output;
+        }
+        let newline = format!("\n{}", self.indent(depth));
+        let mut output = output.trim_end().to_string();
+        for comment in comments {
+            if !output.is_empty() {
+                output += &newline;
+            }
+            output += &comment;
+        }
+        if !BREAK_LINE_RULES.contains(&cst.rule) {
+            // コメントの後ろに閉じ括弧などが続かないようにする
+            output += &newline;
+        }
+        output
+    }
+
+    /// cst を文字列にするための関数
+    /// cst の前後に付いたコメントは含めない
+    fn to_string_node(&self, text: &str, cst: &Cst, depth: usize) -> String {
+        // `% satysfi-fmt: skip` の次の要素は元の文字列のまま出力する
+        if self.trivia.is_skipped(cst) {
+            return self.verbatim(text, cst);
+        }
+
         // インデントを制御するための変数
         let new_depth = match cst.rule {
             Rule::block_text | Rule::cmd_text_arg | Rule::record | Rule::type_record => depth + 1,
@@ -1053,18 +1508,18 @@ impl<'a> Formatter<'a> {
             Rule::ctrl_then | Rule::ctrl_else => depth + 1,
             _ => depth,
         };
-        let start_indent =
-            "\n".to_string() + &indent_space(self.option.tab_size as usize, new_depth);
-        let end_indent = "\n".to_string() + &indent_space(self.option.tab_size as usize, depth);
+        let start_indent = "\n".to_string() + &self.indent(new_depth);
+        let end_indent = "\n".to_string() + &self.indent(depth);
 
         let output = self.to_string_cst_inner(text, cst, new_depth);
+        let output = self.push_dangling(text, cst, output, new_depth);
         let self_text = text.get(cst.span.start..cst.span.end).unwrap().to_string();
 
-        use satysfi_parser::Rule;
         // 中身をそのまま返すものは output をそのまま返す
         // self_text は元の文字列をそのまま返したいときに使用
         match cst.rule {
-            Rule::comments => to_comment_string(self_text) + &end_indent,
+            // コメントは Trivia として要素に付けるので通常は現れない
+            Rule::comments => to_comment_string(self_text),
             // header
             // stage の次は必ず改行する
             Rule::stage => "@stage: ".to_string() + &self_text + "\n\n",
@@ -1179,38 +1634,90 @@ impl<'a> Formatter<'a> {
                 let trimed_self_text: String = self_text.split(char::is_whitespace).collect();
                 if output.is_empty() {
                     "[]".to_string()
-                } else if trimed_self_text.len() < 15 {
-                    // list の文字の長さが十分に短い easy tableの [l;c;r;] など
-                    let inner = output
-                        .split('\n')
-                        .into_iter()
-                        .map(|line| line.trim().to_string())
-                        .filter(|line| !line.is_empty())
-                        .collect::<Vec<String>>()
-                        .join("");
-                    format!("[{inner}]")
                 } else {
-                    format!("[{start_indent}{output}{end_indent}]")
+                    // 要素が全て1行で、max_width に収まる場合は1行にまとめる
+                    let single_line = output.lines().all(|line| line.trim_end().ends_with(';'));
+                    let inner = output
+                        .lines()
+                        .map(|line| line.trim())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let inline = format!("[{}]", inner.trim_end_matches(';'));
+                    let too_long = self
+                        .option
+                        .list_break_width
+                        .is_some_and(|width| trimed_self_text.len() >= width);
+                    if single_line
+                        && !too_long
+                        && self.fits(cst, depth, &inline)
+                        && !self.trivia.contains(cst.span)
+                    {
+                        inline
+                    } else {
+                        format!("[{start_indent}{output}{end_indent}]")
+                    }
                 }
             }
             Rule::record | Rule::type_record => {
-                if cst.inner.len() > 1 {
-                    // 2 つ以上のときは改行
+                let has_comment = self.trivia.contains(cst.span);
+                let fields = cst
+                    .inner
+                    .iter()
+                    .filter(|inner| inner.rule != Rule::unary)
+                    .count();
+                let inline = if cst.inner.len() == 1 {
+                    format!("(|{output}|)")
+                } else {
+                    let inner = output
+                        .split('\n')
+                        .map(|line| line.trim())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    format!("(|{}|)", inner.trim_end_matches(';'))
+                };
+                let break_line = if self
+                    .option
+                    .record_break_fields
+                    .is_some_and(|fields| cst.inner.len() > fields)
+                {
+                    // 要素が多いときは改行
+                    true
+                } else if cst.inner.len() == 1 {
+                    // 1つだけの時は、収まらない場合かコメントがある場合のみ改行
+                    has_comment || !output.contains('\n') && !self.fits(cst, depth, &inline)
+                } else {
+                    has_comment
+                        || output.lines().count() != fields
+                        || !self.fits(cst, depth, &inline)
+                };
+                if break_line {
                     format!("(|{start_indent}{output}{end_indent}|)")
                 } else {
-                    // 1つだけの時は、改行しない
-                    format!("(|{output}|)")
+                    inline
                 }
             }
             Rule::record_unit => output,
-            Rule::tuple => format!("({output})"),
+            Rule::tuple => {
+                let inline = format!("({output})");
+                let has_comment = self.trivia.contains(cst.span);
+                if output.contains('\n') || has_comment || self.fits(cst, depth, &inline) {
+                    inline
+                } else {
+                    // 1行に収まらない場合は要素ごとに改行する
+                    let newline = format!("\n{}", self.indent(depth + 1));
+                    let inner = cst
+                        .inner
+                        .iter()
+                        .map(|inner| self.to_string_cst_on_new_line(text, inner, depth + 1))
+                        .collect::<Vec<_>>()
+                        .join(&format!(",{newline}"));
+                    format!("({newline}{inner}{end_indent})")
+                }
+            }
             Rule::bin_operator => {
                 if self_text == "|>" {
                     // 1つ深くする
-                    format!(
-                        "{start_indent}{}{self_text}",
-                        indent_space(self.option.tab_size as usize, 1)
-                    )
+                    format!("{start_indent}{}{self_text}", self.indent(1))
                 } else {
                     self_text
                 }
@@ -1245,9 +1752,11 @@ impl<'a> Formatter<'a> {
                 let start_arg = self_text.chars().next().unwrap();
                 let end_arg = self_text.chars().last().unwrap();
                 // コメントで開始 or 改行を含んでいたら、改行を入れる
-                let include_comment = output.starts_with('%');
-                let include_kaigyou =
-                    output.find('\n').is_some() || start_arg == '<' || include_comment;
+                // 元の文字列のまま出力する要素の中の改行も含める
+                let include_comment = output.starts_with('%') || self.trivia.contains(cst.span);
+                let include_kaigyou = self.restore_verbatim(text, output).contains('\n')
+                    || start_arg == '<'
+                    || include_comment;
                 if output.starts_with("%\n") {
                     if include_kaigyou {
                         format!("{start_arg}{output}{end_indent}{end_arg}")
@@ -1285,7 +1794,10 @@ impl<'a> Formatter<'a> {
             }
 
             Rule::block_cmd_name => self_text,
-            Rule::math_cmd => self_text.trim().to_string(),
+            Rule::math_cmd => match self.option.command_space {
+                CommandSpace::Auto => self_text.trim().to_string(),
+                CommandSpace::Always | CommandSpace::Never => self.math_cmd_string(text, cst),
+            },
             Rule::math_cmd_name => self_text,
             Rule::math_cmd_expr_arg => output,
             Rule::math_cmd_expr_option => format!(":?{output}"),
@@ -1339,10 +1851,7 @@ impl<'a> Formatter<'a> {
             // horizontal
             Rule::horizontal_single => output,
             Rule::horizontal_list => {
-                let sep = format!(
-                    "\n{}",
-                    indent_space(self.option.tab_size as usize, new_depth)
-                );
+                let sep = format!("\n{}", self.indent(new_depth));
                 let output = self_text
                     .split('\n')
                     .into_iter()
@@ -1356,20 +1865,21 @@ impl<'a> Formatter<'a> {
                     .collect::<Vec<String>>()
                     .join(&sep);
                 // output
-                format!(
-                    "{}{output}",
-                    indent_space(self.option.tab_size as usize, new_depth)
-                )
+                format!("{}{output}", self.indent(new_depth))
             }
             Rule::horizontal_bullet_list => output, // TODO
             Rule::horizontal_bullet => output,      // TODO
             Rule::horizontal_bullet_star => {
-                " ".repeat(self.option.tab_size as usize / 2)
-                    .repeat(self_text.len() - 1)
-                    + &self_text
+                // `*` の数に応じて半分のインデント (タブの場合は1つ) を入れる
+                let nest = self_text.len() - 1;
+                if self.option.use_tabs {
+                    indent_tab(nest) + &self_text
+                } else {
+                    indent_space(self.option.indent_width / 2 * nest) + &self_text
+                }
             }
             Rule::regular_text => {
-                let sep = format!("\n{}", indent_space(self.option.tab_size as usize, depth));
+                let sep = format!("\n{}", self.indent(depth));
                 let output = self_text
                     .split('\n')
                     .into_iter()
@@ -1405,7 +1915,7 @@ impl<'a> Formatter<'a> {
                 }
             }
             Rule::horizontal_escaped_char => self_text,
-            Rule::inline_text_embedding => format!("#{output};"),
+            Rule::inline_text_embedding => self.verbatim(text, cst),
 
             // vertical
             Rule::vertical => output, // インデント制御のため、<> はverticalの親で処理
@@ -1417,7 +1927,7 @@ impl<'a> Formatter<'a> {
             Rule::const_int => self_text,
             Rule::const_float => self_text,
             Rule::const_length => self_text,
-            Rule::const_string => self_text,
+            Rule::const_string => self.verbatim(text, cst),
 
             // math
             Rule::math_single => output, // TODO
@@ -1463,7 +1973,12 @@ impl<'a> Formatter<'a> {
         }
     }
 }
-#[inline]
-fn indent_space(unit: usize, depth: usize) -> String {
-    " ".repeat(unit * depth)
+
+/// 各行の末尾スペースを全て除去する
+fn trim_line_end(output: &str) -> String {
+    output
+        .split('\n')
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
@@ -1,10 +1,6 @@
 #[inline]
-pub fn indent_space(depth: usize) -> String {
-    let mut result = String::new();
-    for _ in 0..depth {
-        result.push(' ');
-    }
-    result
+pub fn indent_space(width: usize) -> String {
+    " ".repeat(width)
 }
 
 #[inline]
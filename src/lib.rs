@@ -1,21 +1,47 @@
 mod comment;
+mod config;
+mod diff;
+mod error;
+mod folding;
 mod formatter;
 mod helper;
+pub mod lsp;
+mod options;
+mod position;
+mod range;
 mod reserved_words;
+mod semantic;
+mod symbol;
 #[cfg(test)]
 mod tests;
 mod visualize;
 
-use comment::*;
+pub use config::{Config, CONFIG_FILE_NAMES};
+pub use diff::unified_diff;
+pub use error::{ConfigError, FormatError};
 use formatter::Formatter;
-use lspower::lsp::{FormattingOptions, TextEdit};
+use lspower::lsp::{
+    Diagnostic, DocumentSymbol, FoldingRange, Position, Range, SemanticToken, TextEdit,
+};
+pub use options::{CommandSpace, FormatOptions};
+pub use position::PositionEncoding;
 use satysfi_parser::{grammar, CstText};
+pub use semantic::TOKEN_TYPES;
 pub use visualize::*;
 
+/// satysfi の文字列を parse する
+/// 失敗した場合はエラー位置と期待していた規則を返す
+fn parse(input: &str) -> Result<CstText, FormatError> {
+    CstText::parse(input, grammar::program).map_err(|err| {
+        let expected = err.1.iter().map(|token| token.to_string()).collect();
+        FormatError::new(input, err.0.line, err.0.column, expected)
+    })
+}
+
 /// satysfi の文字列を渡すと format したものを返す
 /// * `input` - satysfi のコード
-/// * `output` - format された文字列
-pub fn format(input: &str, option: FormattingOptions) -> String {
+/// * `output` - format された文字列。parse に失敗した場合は `FormatError`
+pub fn try_format(input: &str, option: FormatOptions) -> Result<String, FormatError> {
     /*
     CstText {
         text: string,
@@ -23,59 +49,146 @@ pub fn format(input: &str, option: FormattingOptions) -> String {
         cst: Cst,
     }
     */
-    let csttext = CstText::parse(input, grammar::program);
-    if csttext.is_err() {
-        let err = csttext.unwrap_err();
-        let line = err.0.line;
-        let col = err.0.column;
-        eprintln!(
-            "disable to format\n[parse error] line: {}, column: {}",
-            line, col
-        );
-        return input.to_string();
-    }
-    let csttext = csttext.unwrap();
-    let csttext = csttext_insert_comments(csttext);
+    let csttext = parse(input)?;
     let formatter = Formatter::new(&csttext, option);
 
     #[cfg(debug_assertions)]
     visualize_csttext_tree(&csttext);
 
     let depth = 0;
-    
 
-    formatter.format(input, &csttext.cst, depth)
+    Ok(formatter.format(input, &csttext.cst, depth))
 }
 
-pub fn formatting(input: &str, option: FormattingOptions) -> Vec<TextEdit> {
-    let csttext = CstText::parse(input, grammar::program);
-    if csttext.is_err() {
-        let err = csttext.unwrap_err();
-        let line = err.0.line;
-        let col = err.0.column;
-        eprintln!(
-            "disable to format\n[parse error] line: {}, column: {}",
-            line, col
-        );
-        return Vec::new();
+/// satysfi の文字列を渡すと format したものを返す
+/// * `input` - satysfi のコード
+/// * `output` - format された文字列。parse に失敗した場合は `input` をそのまま返す
+pub fn format(input: &str, option: FormatOptions) -> String {
+    try_format(input, option).unwrap_or_else(|_| input.to_string())
+}
+
+/// 選択範囲を含む最小のブロックコマンド、let 文だけを format する
+/// 選択範囲が vertical や文書直下の複数の要素にまたがる場合は、重なる要素をそれぞれ format する
+/// それ以外の部分は元の文字列のまま残す
+/// * `range` - 選択範囲 (byte offset)
+pub fn format_range(
+    input: &str,
+    range: std::ops::Range<usize>,
+    option: FormatOptions,
+) -> Result<String, FormatError> {
+    let csttext = parse(input)?;
+    let formatter = Formatter::new(&csttext, option.clone());
+
+    let mut output = input.to_string();
+    // 後ろの要素から置き換えて、前の要素の位置がずれないようにする
+    for cst in range::target_nodes(&csttext.cst, &range).into_iter().rev() {
+        let text = &input[cst.span.start..cst.span.end];
+        let start = cst.span.start + (text.len() - text.trim_start().len());
+        let end = cst.span.start + text.trim_end().len();
+        // 元のインデントの深さのまま format する
+        let depth = range::indent_depth(input, start, &option);
+        output.replace_range(start..end, &formatter.format_cst(input, cst, depth));
+    }
+    Ok(output)
+}
+
+/// `offset` の直前で閉じられた `cmd_text_arg`, `block_text`, `record` を format する
+/// 閉じ括弧が開き括弧の行と同じインデントになるように、開き括弧の行の深さで format する
+/// 改行の直後の場合は、改行の前で閉じられたものを対象にする
+/// * `offset` - 入力した文字の直後の位置 (byte offset)
+pub fn format_on_type(
+    input: &str,
+    offset: usize,
+    option: FormatOptions,
+) -> Result<String, FormatError> {
+    let csttext = parse(input)?;
+    let formatter = Formatter::new(&csttext, option.clone());
+
+    let mut output = input.to_string();
+    let end = input[..offset].trim_end().len();
+    if let Some(cst) = range::closed_node(input, &csttext.cst, end) {
+        let start = cst.span.start;
+        let depth = range::indent_depth(input, start, &option);
+        output.replace_range(start..end, &formatter.format_cst(input, cst, depth));
+    }
+    Ok(output)
+}
+
+/// parse error を LSP の Diagnostic として返す
+/// parse に成功した場合は空の Vec を返す
+pub fn diagnostics(input: &str, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    match parse(input) {
+        Ok(_) => Vec::new(),
+        Err(err) => vec![err.to_diagnostic(input, encoding)],
+    }
+}
+
+/// モジュール、定義、見出しのアウトラインを LSP の DocumentSymbol として返す
+/// parse に失敗した場合は空の Vec を返す
+pub fn document_symbols(input: &str, encoding: PositionEncoding) -> Vec<DocumentSymbol> {
+    match parse(input) {
+        Ok(csttext) => symbol::SymbolCollector::new(input, encoding).collect(&csttext.cst),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// ブロック、モジュール、連続するコメント行の折りたたみ範囲を LSP の FoldingRange として返す
+/// parse に失敗した場合は空の Vec を返す
+pub fn folding_ranges(input: &str) -> Vec<FoldingRange> {
+    match parse(input) {
+        Ok(csttext) => folding::FoldingCollector::new(input).collect(&csttext),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// コマンド名、変数、型、予約語、リテラル、コメントなどを LSP の semantic tokens として返す
+/// token の種類は `TOKEN_TYPES` の index で表す
+/// parse に失敗した場合は空の Vec を返す
+pub fn semantic_tokens(input: &str, encoding: PositionEncoding) -> Vec<SemanticToken> {
+    match parse(input) {
+        Ok(csttext) => semantic::TokenCollector::new(input, encoding).collect(&csttext),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// format 結果を LSP の TextEdit として返す
+/// 変更のあった箇所ごとに TextEdit を作り、位置は `encoding` の単位で数える
+/// parse に失敗した場合は空の Vec を返す
+pub fn formatting(input: &str, option: FormatOptions, encoding: PositionEncoding) -> Vec<TextEdit> {
+    match try_format(input, option) {
+        Ok(output) => diff::text_edits(input, &output, encoding),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// 選択範囲の format 結果を LSP の TextEdit として返す
+/// `range` と TextEdit の位置は `encoding` の単位で数える
+/// parse に失敗した場合は空の Vec を返す
+pub fn range_formatting(
+    input: &str,
+    range: Range,
+    option: FormatOptions,
+    encoding: PositionEncoding,
+) -> Vec<TextEdit> {
+    let range = encoding.offset(input, range.start)..encoding.offset(input, range.end);
+    match format_range(input, range, option) {
+        Ok(output) => diff::text_edits(input, &output, encoding),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// 文字を入力したときの format 結果を LSP の TextEdit として返す
+/// `position` と TextEdit の位置は `encoding` の単位で数える
+/// parse に失敗した場合は空の Vec を返す
+pub fn on_type_formatting(
+    input: &str,
+    position: Position,
+    option: FormatOptions,
+    encoding: PositionEncoding,
+) -> Vec<TextEdit> {
+    let offset = encoding.offset(input, position);
+    match format_on_type(input, offset, option) {
+        Ok(output) => diff::text_edits(input, &output, encoding),
+        Err(_) => Vec::new(),
     }
-    let csttext = csttext.unwrap();
-    let csttext = csttext_insert_comments(csttext);
-    let formatter = Formatter::new(&csttext, option);
-    let output = formatter.format(input, &csttext.cst, 0);
-    let mut edits = Vec::new();
-    edits.push(TextEdit {
-        range: lspower::lsp::Range {
-            start: lspower::lsp::Position {
-                line: 0,
-                character: 0,
-            },
-            end: lspower::lsp::Position {
-                line: csttext.lines.len() as u32,
-                character: csttext.text.split('\n').last().unwrap().len() as u32,
-            },
-        },
-        new_text: output,
-    });
-    edits
 }
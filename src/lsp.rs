@@ -0,0 +1,354 @@
+use crate::{
+    diagnostics, document_symbols, folding_ranges, formatting, on_type_formatting,
+    range_formatting, semantic_tokens, Config, FormatOptions, PositionEncoding, TOKEN_TYPES,
+};
+use lspower::{
+    jsonrpc::Result,
+    lsp::{
+        DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+        DocumentFormattingParams, DocumentOnTypeFormattingOptions, DocumentOnTypeFormattingParams,
+        DocumentRangeFormattingParams, DocumentSymbolParams, DocumentSymbolResponse, FoldingRange,
+        FoldingRangeParams, FoldingRangeProviderCapability, FormattingOptions, InitializeParams,
+        InitializeResult, InitializedParams, MessageType, OneOf, SemanticTokens,
+        SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions,
+        SemanticTokensParams, SemanticTokensResult, SemanticTokensServerCapabilities,
+        ServerCapabilities, ServerInfo, TextDocumentSyncCapability, TextDocumentSyncKind,
+        TextDocumentSyncOptions, TextEdit, Url, WillSaveTextDocumentParams,
+    },
+    Client, LanguageServer, LspService, Server,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+/// willSaveWaitUntil で format にかける時間の上限の default
+const WILL_SAVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// ドキュメント全体の format 結果を TextEdit にする関数
+type FormattingFn = dyn Fn(&str, FormatOptions, PositionEncoding) -> Vec<TextEdit> + Send + Sync;
+
+/// willSaveWaitUntil の format が動いている間だけ持つ
+/// format が panic した場合も drop でドキュメントを外す
+struct Running(Arc<Mutex<HashSet<Url>>>, Url);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.0.lock() {
+            running.remove(&self.1);
+        }
+    }
+}
+
+/// satysfi-fmt の language server
+/// 開いているドキュメントの内容を保持し、format の要求に応える
+pub struct Backend {
+    client: Client,
+    /// 開いているドキュメントの内容
+    documents: RwLock<HashMap<Url, String>>,
+    /// initialize で client と決めた Position の単位
+    encoding: RwLock<PositionEncoding>,
+    /// willSaveWaitUntil で format にかける時間の上限
+    will_save_timeout: RwLock<Duration>,
+    /// 最後に client から受け取った FormattingOptions
+    client_options: RwLock<Option<FormattingOptions>>,
+    /// textDocument/formatting と willSaveWaitUntil で使う format
+    formatting: Arc<FormattingFn>,
+    /// willSaveWaitUntil の format がまだ終わっていないドキュメント
+    /// 時間切れになった format は止められないので、終わるまで同じドキュメントの format を始めない
+    will_save_running: Arc<Mutex<HashSet<Url>>>,
+}
+
+impl Backend {
+    pub fn new(client: Client) -> Self {
+        Self::with_formatting(client, Arc::new(formatting))
+    }
+
+    /// format に使う関数を指定して作る
+    pub(crate) fn with_formatting(client: Client, formatting: Arc<FormattingFn>) -> Self {
+        Self {
+            client,
+            documents: RwLock::new(HashMap::new()),
+            encoding: RwLock::new(PositionEncoding::default()),
+            will_save_timeout: RwLock::new(WILL_SAVE_TIMEOUT),
+            client_options: RwLock::new(None),
+            formatting,
+            will_save_running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// 開いているドキュメントの内容を返す
+    fn document(&self, uri: &Url) -> Option<String> {
+        self.documents.read().unwrap().get(uri).cloned()
+    }
+
+    fn encoding(&self) -> PositionEncoding {
+        *self.encoding.read().unwrap()
+    }
+
+    /// format に使う FormatOptions を決める
+    /// default、client の FormattingOptions、ドキュメントの位置から探した設定ファイルの順に上書きする
+    /// willSaveWaitUntil のように FormattingOptions が送られてこない場合は最後に受け取ったものを使う
+    fn format_options(&self, uri: &Url, options: Option<FormattingOptions>) -> FormatOptions {
+        let options = match options {
+            Some(options) => {
+                *self.client_options.write().unwrap() = Some(options.clone());
+                Some(options)
+            }
+            None => self.client_options.read().unwrap().clone(),
+        };
+        let client = Config::from(options.map(FormatOptions::from).unwrap_or_default());
+        // 設定ファイルがない、または読み込めない場合は client の設定をそのまま使う
+        let config = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| path.parent().map(Config::discover))
+            .and_then(|config| config.ok())
+            .map(|(config, _)| config)
+            .unwrap_or_default();
+        client.merge(config).format_options()
+    }
+
+    /// parse error を diagnostics として送る
+    /// parse に成功した場合は空の diagnostics を送り、前回のエラーを消す
+    async fn publish_diagnostics(&self, uri: Url, text: &str, version: i32) {
+        let diagnostics = diagnostics(text, self.encoding());
+        self.client
+            .publish_diagnostics(uri, diagnostics, Some(version))
+            .await;
+    }
+}
+
+#[lspower::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        // client が offsetEncoding (clangd の拡張) を送ってきた場合だけ encoding を選んで返す
+        // 送ってこない場合は LSP の default の UTF-16 を使う
+        // LSP 3.17 の general.positionEncodings は lsp-types が未対応なので読まない
+        let offered = params.capabilities.offset_encoding;
+        let encoding = offered
+            .as_deref()
+            .map(PositionEncoding::negotiate)
+            .unwrap_or_default();
+        *self.encoding.write().unwrap() = encoding;
+
+        // initializationOptions の willSaveWaitUntilTimeout (ミリ秒) で時間の上限を変えられる
+        let timeout = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("willSaveWaitUntilTimeout"))
+            .and_then(|timeout| timeout.as_u64());
+        if let Some(timeout) = timeout {
+            *self.will_save_timeout.write().unwrap() = Duration::from_millis(timeout);
+        }
+
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::FULL),
+                        will_save_wait_until: Some(true),
+                        ..Default::default()
+                    },
+                )),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            legend: SemanticTokensLegend {
+                                token_types: TOKEN_TYPES.to_vec(),
+                                token_modifiers: Vec::new(),
+                            },
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            ..Default::default()
+                        },
+                    ),
+                ),
+                // `|)` は `)` で受け取り、record が閉じられたかどうかは CST で判断する
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "}".to_string(),
+                    more_trigger_character: Some(
+                        [">", ")", "\n"].iter().map(|ch| ch.to_string()).collect(),
+                    ),
+                }),
+                ..Default::default()
+            },
+            server_info: Some(ServerInfo {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+            offset_encoding: offered.map(|_| encoding.as_str().to_string()),
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.client
+            .log_message(MessageType::INFO, "satysfi-fmt language server initialized")
+            .await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let document = params.text_document;
+        self.publish_diagnostics(document.uri.clone(), &document.text, document.version)
+            .await;
+        self.documents
+            .write()
+            .unwrap()
+            .insert(document.uri, document.text);
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // FULL sync なので最後の変更がドキュメント全体になる
+        if let Some(change) = params.content_changes.into_iter().last() {
+            let document = params.text_document;
+            self.publish_diagnostics(document.uri.clone(), &change.text, document.version)
+                .await;
+            self.documents
+                .write()
+                .unwrap()
+                .insert(document.uri, change.text);
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.write().unwrap().remove(&uri);
+        // 閉じたドキュメントの diagnostics は消しておく
+        self.client.publish_diagnostics(uri, Vec::new(), None).await;
+    }
+
+    async fn will_save_wait_until(
+        &self,
+        params: WillSaveTextDocumentParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        let text = match self.document(&uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        if !self.will_save_running.lock().unwrap().insert(uri.clone()) {
+            let message = format!("formatting {} on save is still running, skipped", uri);
+            self.client.log_message(MessageType::WARNING, message).await;
+            return Ok(None);
+        }
+        let running = Running(self.will_save_running.clone(), uri.clone());
+        let option = self.format_options(&uri, None);
+        let encoding = self.encoding();
+        let timeout = *self.will_save_timeout.read().unwrap();
+
+        // 保存を止めないように、時間内に終わらなければ何もしない
+        // 時間切れになっても format は最後まで動くので、終わったときに will_save_running から外す
+        let formatting = self.formatting.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let _running = running;
+            formatting(&text, option, encoding)
+        });
+        match tokio::time::timeout(timeout, task).await {
+            Ok(Ok(edits)) => Ok(Some(edits)),
+            Ok(Err(_)) => Ok(None),
+            Err(_) => {
+                let message = format!(
+                    "formatting {} on save took longer than {}ms, skipped",
+                    uri,
+                    timeout.as_millis()
+                );
+                self.client.log_message(MessageType::WARNING, message).await;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        let text = match self.document(&uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let option = self.format_options(&uri, Some(params.options));
+        Ok(Some((self.formatting)(&text, option, self.encoding())))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        let text = match self.document(&uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let option = self.format_options(&uri, Some(params.options));
+        let edits = range_formatting(&text, params.range, option, self.encoding());
+        Ok(Some(edits))
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let text = match self.document(&uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let option = self.format_options(&uri, Some(params.options));
+        let edits = on_type_formatting(&text, position.position, option, self.encoding());
+        Ok(Some(edits))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let text = match self.document(&params.text_document.uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let symbols = document_symbols(&text, self.encoding());
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let text = match self.document(&params.text_document.uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        Ok(Some(folding_ranges(&text)))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        let text = match self.document(&params.text_document.uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let tokens = SemanticTokens {
+            result_id: None,
+            data: semantic_tokens(&text, self.encoding()),
+        };
+        Ok(Some(SemanticTokensResult::Tokens(tokens)))
+    }
+}
+
+/// stdin / stdout で LSP を話す server を起動する
+pub async fn run_stdio() {
+    let stdin = tokio::io::stdin();
+    let stdout = tokio::io::stdout();
+    let (service, messages) = LspService::new(Backend::new);
+    Server::new(stdin, stdout)
+        .interleave(messages)
+        .serve(service)
+        .await;
+}
@@ -1,40 +1,378 @@
-use clap::Parser;
-use lspower::lsp::FormattingOptions;
-use satysfi_formatter::format;
-use std::{fs, path::PathBuf};
+use clap::{CommandFactory, ErrorKind, Parser};
+use glob::Pattern;
+use satysfi_formatter::{try_format, unified_diff, CommandSpace, Config, ConfigError};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// format が必要なファイルが存在した (--check)
+const EXIT_REFORMAT: i32 = 1;
+// 2 は clap が引数のエラーに使用する
+/// parse に失敗した
+const EXIT_PARSE_ERROR: i32 = 3;
+/// ファイルの読み書き、または設定ファイルの読み込みに失敗した
+const EXIT_IO_ERROR: i32 = 4;
+
+/// ディレクトリから探索するファイルの拡張子
+const EXTENSIONS: [&str; 3] = ["saty", "satyh", "satyg"];
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
-    /// input file
-    #[clap(parse(from_os_str), value_name = "FILE")]
-    file: PathBuf,
+    /// input files or directories (directories are searched recursively, `-` reads stdin)
+    #[clap(
+        parse(from_os_str),
+        value_name = "FILE",
+        required_unless_present_any = &["stdin", "print-config"]
+    )]
+    files: Vec<PathBuf>,
+    /// read the source from stdin and write the result to stdout
+    #[clap(long, conflicts_with_all = &["write", "output"])]
+    stdin: bool,
+    /// path of the source read from stdin (used in messages and to find the config file)
+    #[clap(long, parse(from_os_str), value_name = "PATH")]
+    stdin_filepath: Option<PathBuf>,
     /// write to input file
     #[clap(short, long)]
     write: bool,
-    /// output file
+    /// output file (only with a single input file)
     #[clap(short, long)]
     output: Option<PathBuf>,
-    /// indent size
-    #[clap(short, long, default_value_t = 4)]
-    indent_space: usize,
-    /// Add space before arguments in command
+    /// check whether the input is formatted without writing anything
+    /// (exit status: 1 = would reformat, 3 = parse error, 4 = I/O or config error)
+    #[clap(long, conflicts_with_all = &["write", "output"])]
+    check: bool,
+    /// print a unified diff of the changes instead of the formatted text
+    #[clap(long, conflicts_with_all = &["write", "output"])]
+    diff: bool,
+    /// skip files and directories matching the glob pattern
+    #[clap(long, value_name = "PATTERN")]
+    ignore: Vec<Pattern>,
+    /// print the effective configuration and exit
+    #[clap(long)]
+    print_config: bool,
+    /// indent size [default: 4]
+    #[clap(short, long)]
+    indent_space: Option<usize>,
+    /// indent with tabs instead of spaces
+    #[clap(long)]
+    tabs: bool,
+    /// maximum line width [default: 100]
+    #[clap(long)]
+    max_width: Option<usize>,
+    /// Add space before arguments in command, also in math commands
+    /// (by default only inline and block commands are spaced)
     #[clap(long)]
     cspace: bool,
+    /// Remove space before arguments in command
+    #[clap(long, conflicts_with = "cspace")]
+    no_cspace: bool,
+}
+
+/// 1ファイルごとの format 結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Unchanged,
+    Reformatted,
+    ParseError,
+    IoError,
+    ConfigError,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Unchanged => "unchanged",
+            Status::Reformatted => "reformatted",
+            Status::ParseError => "parse error",
+            Status::IoError => "I/O error",
+            Status::ConfigError => "config error",
+        }
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
-    let code = fs::read_to_string(&cli.file).expect("Failed to read file");
-    let option = FormattingOptions {
-        tab_size: cli.indent_space as u32,
-        ..Default::default()
+
+    if cli.print_config {
+        print_config(&cli);
+    }
+
+    if cli.stdin || cli.files.iter().any(|path| path == Path::new("-")) {
+        if cli.files.iter().any(|path| path != Path::new("-")) || cli.write || cli.output.is_some()
+        {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "stdin cannot be combined with input files, --write or --output",
+                )
+                .exit();
+        }
+        let status = format_stdin(&cli);
+        exit(&cli, &[status]);
+    }
+
+    let mut results = Vec::new();
+    let mut files = Vec::new();
+    for path in cli.files.iter() {
+        collect_files(path, &cli.ignore, &mut files, &mut results);
+    }
+
+    // 空のディレクトリだけが指定された場合は対象のファイルがないだけなので、何もせずに終了する
+    let single = files.len() + results.len() <= 1;
+    if !single && (cli.output.is_some() || !(cli.write || cli.check || cli.diff)) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "multiple input files require --write, --check or --diff",
+            )
+            .exit();
+    }
+
+    for path in files {
+        let status = format_file(&cli, &path);
+        results.push((path, status));
+    }
+
+    if !single {
+        // ファイルごとの結果をまとめて表示する
+        for (path, status) in results.iter() {
+            eprintln!("{:<12}{}", status.label(), path.display());
+        }
+    }
+
+    let statuses = results
+        .iter()
+        .map(|(_, status)| *status)
+        .collect::<Vec<_>>();
+    exit(&cli, &statuses);
+}
+
+/// format 結果から終了ステータスを決めて終了する
+fn exit(cli: &Cli, statuses: &[Status]) -> ! {
+    let has = |status| statuses.contains(&status);
+    if has(Status::IoError) || has(Status::ConfigError) {
+        process::exit(EXIT_IO_ERROR);
+    } else if has(Status::ParseError) {
+        process::exit(EXIT_PARSE_ERROR);
+    } else if cli.check && has(Status::Reformatted) {
+        process::exit(EXIT_REFORMAT);
+    }
+    process::exit(0);
+}
+
+/// format 対象のファイルを集める
+/// ディレクトリの場合は再帰的に探索し、拡張子が EXTENSIONS のものだけを対象にする
+/// 読み込めなかったディレクトリやエントリは `errors` に追加し、残りの探索を続ける
+fn collect_files(
+    path: &Path,
+    ignore: &[Pattern],
+    files: &mut Vec<PathBuf>,
+    errors: &mut Vec<(PathBuf, Status)>,
+) {
+    let ignored = ignore.iter().any(|pattern| {
+        pattern.matches_path(path)
+            || path
+                .file_name()
+                .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+    });
+    if ignored {
+        return;
+    }
+    if !path.is_dir() {
+        // 明示的に指定されたファイルは拡張子に関わらず対象にする
+        files.push(path.to_path_buf());
+        return;
+    }
+
+    let mut report = |path: &Path, err: io::Error| {
+        eprintln!("failed to read {}: {}", path.display(), err);
+        errors.push((path.to_path_buf(), Status::IoError));
+    };
+    let mut entries = Vec::new();
+    match fs::read_dir(path) {
+        Ok(dir) => {
+            for entry in dir {
+                match entry {
+                    Ok(entry) => entries.push(entry.path()),
+                    Err(err) => report(path, err),
+                }
+            }
+        }
+        Err(err) => report(path, err),
+    }
+    entries.sort();
+    for entry in entries {
+        let is_target = entry
+            .extension()
+            .is_some_and(|ext| EXTENSIONS.iter().any(|target| ext == *target));
+        if entry.is_dir() || is_target {
+            collect_files(&entry, ignore, files, errors);
+        }
+    }
+}
+
+/// 1つのファイルを format して結果を返す
+fn format_file(cli: &Cli, path: &Path) -> Status {
+    let code = match fs::read_to_string(path) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path.display(), err);
+            return Status::IoError;
+        }
+    };
+    let (output, status) = match format_code(cli, path, Some(path), &code) {
+        Ok(output) => output,
+        Err(status) => return status,
+    };
+    if cli.check || cli.diff {
+        return status;
+    }
+
+    let result = match (&cli.output, cli.write) {
+        (Some(output_path), _) => {
+            fs::write(output_path, &output).map_err(|err| (output_path.as_path(), err))
+        }
+        (None, true) => fs::write(path, &output).map_err(|err| (path, err)),
+        (None, false) => {
+            println!("{}", output);
+            Ok(())
+        }
+    };
+    match result {
+        Ok(()) => status,
+        Err((path, err)) => {
+            eprintln!("failed to write {}: {}", path.display(), err);
+            Status::IoError
+        }
+    }
+}
+
+/// stdin から読み込んで format し、結果を stdout に出力する
+/// stdout には format 結果以外を出力しない
+fn format_stdin(cli: &Cli) -> Status {
+    let path = cli
+        .stdin_filepath
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("<stdin>"));
+    let mut code = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut code) {
+        eprintln!("failed to read {}: {}", path.display(), err);
+        return Status::IoError;
+    }
+    let source = cli.stdin_filepath.as_deref();
+    let (output, status) = match format_code(cli, &path, source, &code) {
+        Ok(output) => output,
+        Err(status) => return status,
+    };
+    if !(cli.check || cli.diff) {
+        print!("{}", output);
+    }
+    status
+}
+
+/// 文字列を format する
+/// `source` の位置から設定ファイルを探す
+/// --check, --diff の場合はここで出力する
+fn format_code(
+    cli: &Cli,
+    path: &Path,
+    source: Option<&Path>,
+    code: &str,
+) -> Result<(String, Status), Status> {
+    let option = match load_config(cli, source) {
+        Ok((config, _)) => config.format_options(),
+        Err(err) => {
+            eprintln!("{}", err);
+            return Err(Status::ConfigError);
+        }
+    };
+    let output = match try_format(code, option) {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("disable to format {}\n{}", path.display(), err);
+            return Err(Status::ParseError);
+        }
+    };
+    let status = if output == code {
+        Status::Unchanged
+    } else {
+        Status::Reformatted
+    };
+
+    if cli.diff {
+        print!(
+            "{}",
+            unified_diff(code, &output, &path.display().to_string())
+        );
+    }
+    if cli.check && status == Status::Reformatted && !cli.diff {
+        // 差分があるファイルを列挙する
+        println!("{}", path.display());
+    }
+    Ok((output, status))
+}
+
+/// コマンドライン引数で指定された設定
+fn cli_config(cli: &Cli) -> Config {
+    let command_space = match (cli.cspace, cli.no_cspace) {
+        (true, _) => Some(CommandSpace::Always),
+        (_, true) => Some(CommandSpace::Never),
+        _ => None,
     };
-    let output = format(&code, option);
+    Config {
+        indent_space: cli.indent_space,
+        use_tabs: if cli.tabs { Some(true) } else { None },
+        max_width: cli.max_width,
+        command_space,
+        ..Default::default()
+    }
+}
+
+/// `source` に対応する設定ファイルを読み込み、コマンドライン引数で上書きする
+/// `source` がディレクトリの場合はその中から、ファイルの場合は親ディレクトリから探す
+/// `source` が None の場合は current directory から探す
+fn load_config(cli: &Cli, source: Option<&Path>) -> Result<(Config, Option<PathBuf>), ConfigError> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let dir = source
+        .map(|path| cwd.join(path))
+        .and_then(|path| {
+            if path.is_dir() {
+                Some(path)
+            } else {
+                path.parent().map(Path::to_path_buf)
+            }
+        })
+        .unwrap_or(cwd);
+    let (config, path) = Config::discover(&dir)?;
+    Ok((config.merge(cli_config(cli)), path))
+}
 
-    match (cli.output, cli.write) {
-        (Some(path), _) => fs::write(path, &output).expect("Failed to write file"),
-        (None, true) => fs::write(&cli.file, &output).expect("Failed to write file"),
-        (None, false) => println!("{}", output),
+/// 最初の入力に対して有効な設定を表示して終了する
+fn print_config(cli: &Cli) -> ! {
+    let source = cli
+        .stdin_filepath
+        .as_deref()
+        .or_else(|| cli.files.first().map(PathBuf::as_path))
+        .filter(|path| *path != Path::new("-"));
+    match load_config(cli, source) {
+        Ok((config, path)) => {
+            if let Some(path) = path {
+                println!("# {}", path.display());
+            }
+            let config = Config::defaults().merge(config);
+            print!(
+                "{}",
+                toml::to_string(&config).expect("failed to serialize config")
+            );
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(EXIT_IO_ERROR);
+        }
     }
 }
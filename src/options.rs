@@ -0,0 +1,85 @@
+use lspower::lsp::FormattingOptions;
+use serde::{Deserialize, Serialize};
+
+/// formatter の設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// インデントの幅
+    pub indent_width: usize,
+    /// スペースの代わりにタブでインデントする
+    pub use_tabs: bool,
+    /// タブの表示幅。use_tabs の場合に max_width に収まるかの判定に使う
+    pub tab_width: usize,
+    /// 1行の最大幅。これを超える場合は改行する
+    pub max_width: usize,
+    /// 末尾に改行を入れるかどうか
+    pub insert_final_newline: bool,
+    /// 連続する空行をいくつまで残すか
+    pub max_blank_lines: usize,
+    /// 指定した場合、空白を除いた長さがこれ以上の list は max_width に収まっても要素ごとに改行する
+    /// 指定しなければ max_width に収まるかどうかだけで決める
+    pub list_break_width: Option<usize>,
+    /// 指定した場合、要素数がこれより多い record は max_width に収まっても要素ごとに改行する
+    /// 指定しなければ max_width に収まるかどうかだけで決める
+    pub record_break_fields: Option<usize>,
+    /// コマンド名と引数の間に空白を入れるかどうか
+    pub command_space: CommandSpace,
+    /// 行末のコメントがある行が続くとき、コメントの列を揃える
+    pub align_trailing_comments: bool,
+    /// 引数を元の文字列のまま出力するコマンド (`\code`, `+code` など)
+    pub verbatim_commands: Vec<String>,
+}
+
+/// コマンド名と引数の間の空白 (`+p { ... }` と `+p{ ... }`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandSpace {
+    /// inline, block コマンドには空白を入れ、math コマンドは元の文字列のままにする
+    Auto,
+    /// inline, block, math コマンドの全てで空白を入れる
+    Always,
+    /// inline, block, math コマンドの全てで空白を入れない
+    Never,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            use_tabs: false,
+            tab_width: 4,
+            max_width: 100,
+            insert_final_newline: true,
+            max_blank_lines: 1,
+            list_break_width: None,
+            record_break_fields: None,
+            command_space: CommandSpace::Auto,
+            align_trailing_comments: false,
+            verbatim_commands: ["\\code", "+code", "+console"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// LSP の FormattingOptions から変換する
+/// LSP で指定できない項目は default の値を使う
+/// `FormattingOptions::default()` のように tab_size が 0 の場合はインデントが指定されていないとみなし、
+/// insert_spaces に関わらず default のスペースによるインデントを使う
+/// タブの表示幅は tab_size とする
+impl From<FormattingOptions> for FormatOptions {
+    fn from(option: FormattingOptions) -> Self {
+        let default = FormatOptions::default();
+        let (indent_width, use_tabs, tab_width) = if option.tab_size == 0 {
+            (default.indent_width, default.use_tabs, default.tab_width)
+        } else {
+            let tab_size = option.tab_size as usize;
+            (tab_size, !option.insert_spaces, tab_size)
+        };
+        Self {
+            indent_width,
+            use_tabs,
+            tab_width,
+            insert_final_newline: option.insert_final_newline.unwrap_or(true),
+            ..default
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use lspower::lsp::{Position, Range};
+
+/// LSP の Position の character を数える単位
+/// client と server の間で initialize のときに決める (指定がなければ UTF-16)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    /// byte 単位
+    Utf8,
+    /// UTF-16 の code unit 単位 (LSP の default)
+    #[default]
+    Utf16,
+    /// Unicode の code point 単位
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// LSP で使う名前 (`utf-8`, `utf-16`, `utf-32`)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PositionEncoding::Utf8 => "utf-8",
+            PositionEncoding::Utf16 => "utf-16",
+            PositionEncoding::Utf32 => "utf-32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    /// client が対応している encoding から使うものを選ぶ
+    /// client の希望順に見て、最初に対応しているものを使う
+    pub fn negotiate<S: AsRef<str>>(offered: &[S]) -> Self {
+        offered
+            .iter()
+            .find_map(|name| Self::from_name(name.as_ref()))
+            .unwrap_or_default()
+    }
+
+    /// 文字列の長さをこの encoding の単位で返す
+    pub(crate) fn len(self, s: &str) -> usize {
+        match self {
+            PositionEncoding::Utf8 => s.len(),
+            PositionEncoding::Utf16 => s.encode_utf16().count(),
+            PositionEncoding::Utf32 => s.chars().count(),
+        }
+    }
+
+    /// `text` の byte offset を Position に変換する
+    pub fn position(self, text: &str, offset: usize) -> Position {
+        let before = &text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Position {
+            line: before.matches('\n').count() as u32,
+            character: self.len(&before[line_start..]) as u32,
+        }
+    }
+
+    /// `text` の byte offset の範囲を Range に変換する
+    pub fn range(self, text: &str, span: std::ops::Range<usize>) -> Range {
+        Range {
+            start: self.position(text, span.start),
+            end: self.position(text, span.end),
+        }
+    }
+
+    /// Position を `text` の byte offset に変換する
+    /// 行末や文書の末尾を超える場合はそれぞれの末尾に丸める
+    pub fn offset(self, text: &str, position: Position) -> usize {
+        let mut line_start = 0;
+        for _ in 0..position.line {
+            match text[line_start..].find('\n') {
+                Some(index) => line_start += index + 1,
+                None => return text.len(),
+            }
+        }
+        let line = &text[line_start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+        let mut count = 0;
+        for (index, c) in line.char_indices() {
+            if count >= position.character as usize {
+                return line_start + index;
+            }
+            count += self.len(c.encode_utf8(&mut [0; 4]));
+        }
+        line_start + line.len()
+    }
+}
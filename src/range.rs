@@ -0,0 +1,81 @@
+use crate::FormatOptions;
+use satysfi_parser::{Cst, Rule};
+use std::ops::Range;
+
+/// 範囲 format の単位になる規則
+const TARGET_RULES: [Rule; 10] = [
+    Rule::block_cmd,
+    Rule::let_stmt,
+    Rule::let_rec_stmt,
+    Rule::let_inline_stmt_ctx,
+    Rule::let_inline_stmt_noctx,
+    Rule::let_block_stmt_ctx,
+    Rule::let_block_stmt_noctx,
+    Rule::let_math_stmt,
+    Rule::let_mutable_stmt,
+    Rule::vertical,
+];
+
+/// 選択範囲 `range` を format するために整形し直す節点を返す
+/// 選択範囲を含む最小のブロックコマンドや let 文を1つ返す。
+/// 選択範囲が vertical や文書直下の複数の要素にまたがる場合は、重なる要素を全て返す
+pub fn target_nodes<'a>(cst: &'a Cst, range: &Range<usize>) -> Vec<&'a Cst> {
+    // 選択範囲を含む最も深い節点までの経路
+    let mut path = vec![cst];
+    while let Some(inner) = path
+        .last()
+        .unwrap()
+        .inner
+        .iter()
+        .find(|inner| inner.span.start <= range.start && range.end <= inner.span.end)
+    {
+        path.push(inner);
+    }
+
+    let parent = match path
+        .iter()
+        .rev()
+        .find(|cst| TARGET_RULES.contains(&cst.rule))
+    {
+        Some(cst) if cst.rule != Rule::vertical => return vec![cst],
+        Some(cst) => cst,
+        None => path.last().unwrap(),
+    };
+    // 空の選択範囲 (カーソル位置) でも、その位置を含む要素は対象にする
+    let end = range.end.max(range.start + 1);
+    parent
+        .inner
+        .iter()
+        .filter(|inner| TARGET_RULES.contains(&inner.rule))
+        .filter(|inner| inner.span.start < end && range.start < inner.span.end)
+        .collect()
+}
+
+/// `offset` を含む行のインデントの深さ
+/// タブは1つで1段、スペースは `indent_width` 個で1段として数える
+pub fn indent_depth(input: &str, offset: usize, option: &FormatOptions) -> usize {
+    let line_start = input[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let width = input[line_start..offset]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .map(|c| if c == '\t' { option.indent_width } else { 1 })
+        .sum::<usize>();
+    width / option.indent_width.max(1)
+}
+
+/// on type format で整形し直す、閉じ括弧で終わる規則
+const CLOSED_RULES: [Rule; 3] = [Rule::cmd_text_arg, Rule::block_text, Rule::record];
+
+/// `end` の位置で閉じられた最も内側の `cmd_text_arg`, `block_text`, `record` を返す
+pub fn closed_node<'a>(input: &str, cst: &'a Cst, end: usize) -> Option<&'a Cst> {
+    if !(cst.span.start < end && end <= cst.span.end) {
+        return None;
+    }
+    let inner = cst
+        .inner
+        .iter()
+        .find_map(|inner| closed_node(input, inner, end));
+    let text = &input[cst.span.start..cst.span.end];
+    let closed = CLOSED_RULES.contains(&cst.rule) && cst.span.start + text.trim_end().len() == end;
+    inner.or(if closed { Some(cst) } else { None })
+}
@@ -84,3 +84,50 @@ pub const RESERVED_WORD: ReservedWord = ReservedWord {
     in_stmt: "in",
     of: "of",
 };
+
+impl ReservedWord {
+    /// 全ての予約語
+    pub fn words(&self) -> [ReservedText; 39] {
+        [
+            self.let_block,
+            self.let_math,
+            self.let_mutable,
+            self.type_stmt,
+            self.let_inline,
+            self.constraint,
+            self.inline_command,
+            self.block_command,
+            self.math_command,
+            self.let_rec,
+            self.controls,
+            self.command,
+            self.before,
+            self.module,
+            self.direct,
+            self.struct_stmt,
+            self.cycle,
+            self.match_stmt,
+            self.while_stmt,
+            self.if_stmt,
+            self.else_stmt,
+            self.true_stmt,
+            self.false_stmt,
+            self.open,
+            self.then,
+            self.when,
+            self.with,
+            self.and,
+            self.end,
+            self.fun,
+            self.let_stmt,
+            self.mod_stmt,
+            self.not,
+            self.sig,
+            self.val,
+            self.as_stmt,
+            self.do_stmt,
+            self.in_stmt,
+            self.of,
+        ]
+    }
+}
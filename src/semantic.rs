@@ -0,0 +1,209 @@
+use crate::comment::get_comments;
+use crate::reserved_words::*;
+use crate::PositionEncoding;
+use lspower::lsp::{SemanticToken, SemanticTokenType};
+use satysfi_parser::{Cst, CstText, Rule};
+
+/// semantic tokens の legend
+/// 各 token の種類はこの配列の index で表す
+pub const TOKEN_TYPES: [SemanticTokenType; 10] = [
+    SemanticTokenType::MACRO,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::TYPE_PARAMETER,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::COMMENT,
+];
+
+/// 規則に対応する token の種類
+/// 対応しない規則は子要素を探す
+fn token_type(rule: Rule) -> Option<SemanticTokenType> {
+    let token_type = match rule {
+        Rule::inline_cmd_name | Rule::block_cmd_name | Rule::math_cmd_name | Rule::cmd_name_ptn => {
+            SemanticTokenType::MACRO
+        }
+        Rule::var | Rule::var_ptn => SemanticTokenType::VARIABLE,
+        Rule::module_name => SemanticTokenType::NAMESPACE,
+        Rule::variant_name => SemanticTokenType::ENUM_MEMBER,
+        Rule::type_name => SemanticTokenType::TYPE,
+        Rule::type_param => SemanticTokenType::TYPE_PARAMETER,
+        Rule::const_bool => SemanticTokenType::KEYWORD,
+        Rule::const_string => SemanticTokenType::STRING,
+        Rule::const_int | Rule::const_float | Rule::const_length => SemanticTokenType::NUMBER,
+        _ => return None,
+    };
+    Some(token_type)
+}
+
+/// 地の文や数式の中の規則
+/// 予約語と同じ単語が出てきても keyword にしない
+const TEXT_RULES: [Rule; 12] = [
+    Rule::horizontal_single,
+    Rule::horizontal_list,
+    Rule::horizontal_bullet_list,
+    Rule::horizontal_bullet,
+    Rule::horizontal_text,
+    Rule::regular_text,
+    Rule::math_text,
+    Rule::math_list,
+    Rule::math_single,
+    Rule::math_token,
+    Rule::math_unary,
+    Rule::pkgname,
+];
+
+/// cst から semantic tokens を作る
+pub struct TokenCollector<'a> {
+    text: &'a str,
+    encoding: PositionEncoding,
+    /// 改行文字の byte offset
+    newlines: Vec<usize>,
+    /// (開始位置, 終了位置, 種類) の byte offset
+    tokens: Vec<(usize, usize, u32)>,
+}
+
+impl<'a> TokenCollector<'a> {
+    pub fn new(text: &'a str, encoding: PositionEncoding) -> Self {
+        Self {
+            text,
+            encoding,
+            newlines: text.match_indices('\n').map(|(index, _)| index).collect(),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// 文書全体の semantic tokens を LSP の相対位置の形式で返す
+    pub fn collect(mut self, csttext: &CstText) -> Vec<SemanticToken> {
+        for comment in get_comments(csttext) {
+            self.push(
+                comment.span.start,
+                comment.span.end,
+                SemanticTokenType::COMMENT,
+            );
+        }
+        self.walk(&csttext.cst);
+
+        // 前から順に並べ、コメントなどと重なるものは除く
+        let mut tokens = std::mem::take(&mut self.tokens);
+        tokens.sort();
+        let mut end = 0;
+        tokens.retain(|(start, token_end, _)| {
+            let keep = end <= *start;
+            if keep {
+                end = *token_end;
+            }
+            keep
+        });
+        self.encode(&tokens)
+    }
+
+    fn walk(&mut self, cst: &Cst) {
+        if let Some(token_type) = token_type(cst.rule) {
+            self.push(cst.span.start, cst.span.end, token_type);
+            return;
+        }
+        if !TEXT_RULES.contains(&cst.rule) && !cst.inner.is_empty() {
+            self.keywords(cst);
+        }
+        for inner in cst.inner.iter() {
+            self.walk(inner);
+        }
+    }
+
+    /// 子要素の間の文字列から予約語を探す
+    fn keywords(&mut self, cst: &Cst) {
+        let mut gaps = Vec::new();
+        let mut start = cst.span.start;
+        for inner in cst.inner.iter() {
+            gaps.push((start, inner.span.start));
+            start = inner.span.end;
+        }
+        gaps.push((start, cst.span.end));
+
+        let words = RESERVED_WORD.words();
+        for (gap_start, gap_end) in gaps {
+            let gap = &self.text[gap_start..gap_end];
+            let mut word_start = None;
+            // 末尾に区切り文字を足して、最後の単語も処理する
+            for (index, c) in gap.char_indices().chain(std::iter::once((gap.len(), ' '))) {
+                let is_word = c.is_ascii_alphanumeric() || c == '-' || c == '_';
+                match (word_start, is_word) {
+                    (None, true) => word_start = Some(index),
+                    (Some(start), false) => {
+                        if words.contains(&&gap[start..index]) {
+                            self.push(
+                                gap_start + start,
+                                gap_start + index,
+                                SemanticTokenType::KEYWORD,
+                            );
+                        }
+                        word_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, start: usize, end: usize, token_type: SemanticTokenType) {
+        let index = TOKEN_TYPES
+            .iter()
+            .position(|legend| *legend == token_type)
+            .unwrap() as u32;
+        let text = &self.text[start..end];
+        // 末尾の空白や改行は含めない
+        let end = start + text.trim_end().len();
+        if start < end {
+            self.tokens.push((start, end, index));
+        }
+    }
+
+    /// 1つ前の token からの相対位置に変換する
+    /// 複数行にわたる token は行ごとに分ける
+    fn encode(&self, tokens: &[(usize, usize, u32)]) -> Vec<SemanticToken> {
+        let mut output = Vec::new();
+        let (mut line, mut character) = (0, 0);
+        for (start, end, token_type) in tokens {
+            let mut start = *start;
+            for part in self.text[start..*end].split_inclusive('\n') {
+                let part_end = start + part.trim_end_matches(['\n', '\r']).len();
+                if start < part_end {
+                    let (token_line, token_character) = self.position(start);
+                    let delta_line = token_line - line;
+                    let delta_start = if delta_line == 0 {
+                        token_character - character
+                    } else {
+                        token_character
+                    };
+                    output.push(SemanticToken {
+                        delta_line,
+                        delta_start,
+                        length: self.encoding.len(&self.text[start..part_end]) as u32,
+                        token_type: *token_type,
+                        token_modifiers_bitset: 0,
+                    });
+                    line = token_line;
+                    character = token_character;
+                }
+                start += part.len();
+            }
+        }
+        output
+    }
+
+    /// byte offset の行と、行頭からの位置 (encoding の単位)
+    fn position(&self, offset: usize) -> (u32, u32) {
+        let line = self.newlines.partition_point(|newline| *newline < offset);
+        let line_start = if line == 0 {
+            0
+        } else {
+            self.newlines[line - 1] + 1
+        };
+        let character = self.encoding.len(&self.text[line_start..offset]);
+        (line as u32, character as u32)
+    }
+}
@@ -0,0 +1,201 @@
+use crate::reserved_words::*;
+use crate::PositionEncoding;
+use lspower::lsp::{DocumentSymbol, Range, SymbolKind};
+use satysfi_parser::{Cst, Rule, Span};
+
+/// アウトラインに表示する見出しのブロックコマンド
+const SECTION_COMMANDS: [&str; 5] = [
+    "+chapter",
+    "+section",
+    "+subsection",
+    "+subsubsection",
+    "+part",
+];
+
+/// cst からアウトラインを作る
+pub struct SymbolCollector<'a> {
+    text: &'a str,
+    encoding: PositionEncoding,
+}
+
+impl<'a> SymbolCollector<'a> {
+    pub fn new(text: &'a str, encoding: PositionEncoding) -> Self {
+        Self { text, encoding }
+    }
+
+    /// cst の子孫のうち、アウトラインに表示するものを階層構造で返す
+    pub fn collect(&self, cst: &Cst) -> Vec<DocumentSymbol> {
+        cst.inner
+            .iter()
+            .flat_map(|inner| self.symbols(inner))
+            .collect()
+    }
+
+    /// cst 自身がアウトラインに表示するものであればそれを、そうでなければ子孫を返す
+    fn symbols(&self, cst: &Cst) -> Vec<DocumentSymbol> {
+        match cst.rule {
+            Rule::module_stmt => {
+                let name = find(cst, &[Rule::module_name]).unwrap_or(cst);
+                vec![self.symbol(cst, name, SymbolKind::MODULE, RESERVED_WORD.module)]
+            }
+            Rule::sig_stmt => {
+                let mut symbol = self.symbol(cst, cst, SymbolKind::INTERFACE, RESERVED_WORD.sig);
+                symbol.name = RESERVED_WORD.sig.to_string();
+                vec![symbol]
+            }
+            Rule::struct_stmt => {
+                let mut symbol =
+                    self.symbol(cst, cst, SymbolKind::STRUCT, RESERVED_WORD.struct_stmt);
+                symbol.name = RESERVED_WORD.struct_stmt.to_string();
+                vec![symbol]
+            }
+            Rule::let_stmt => vec![self.definition(cst, RESERVED_WORD.let_stmt)],
+            Rule::let_mutable_stmt => vec![self.definition(cst, RESERVED_WORD.let_mutable)],
+            Rule::let_inline_stmt_ctx | Rule::let_inline_stmt_noctx => {
+                vec![self.definition(cst, RESERVED_WORD.let_inline)]
+            }
+            Rule::let_block_stmt_ctx | Rule::let_block_stmt_noctx => {
+                vec![self.definition(cst, RESERVED_WORD.let_block)]
+            }
+            Rule::let_math_stmt => vec![self.definition(cst, RESERVED_WORD.let_math)],
+            // let-rec f ... and g ... は定義ごとに分ける
+            Rule::let_rec_stmt => cst
+                .inner
+                .iter()
+                .filter(|inner| inner.rule == Rule::let_rec_inner)
+                .map(|inner| self.definition(inner, RESERVED_WORD.let_rec))
+                .collect(),
+            Rule::type_stmt => cst
+                .inner
+                .iter()
+                .filter(|inner| inner.rule == Rule::type_inner)
+                .map(|inner| self.type_definition(inner))
+                .collect(),
+            Rule::sig_val_stmt => vec![self.definition(cst, RESERVED_WORD.val)],
+            Rule::sig_direct_stmt => vec![self.definition(cst, RESERVED_WORD.direct)],
+            Rule::sig_type_stmt => {
+                let name = find(cst, &[Rule::type_name]).unwrap_or(cst);
+                vec![self.leaf(cst, name, SymbolKind::STRUCT, RESERVED_WORD.type_stmt)]
+            }
+            Rule::block_cmd => match self.section(cst) {
+                Some(symbol) => vec![symbol],
+                None => self.collect(cst),
+            },
+            _ => self.collect(cst),
+        }
+    }
+
+    /// 子孫を children に持つ symbol
+    fn symbol(&self, cst: &Cst, name: &Cst, kind: SymbolKind, detail: &str) -> DocumentSymbol {
+        let mut symbol = self.leaf(cst, name, kind, detail);
+        symbol.children = Some(self.collect(cst));
+        symbol
+    }
+
+    /// 子孫を持たない symbol
+    /// `name` の文字列を名前にする
+    fn leaf(&self, cst: &Cst, name: &Cst, kind: SymbolKind, detail: &str) -> DocumentSymbol {
+        #[allow(deprecated)]
+        DocumentSymbol {
+            name: normalize(self.get_text(name.span)),
+            detail: Some(detail.to_string()),
+            kind,
+            tags: None,
+            deprecated: None,
+            range: self.range(cst.span),
+            selection_range: self.range(name.span),
+            children: None,
+        }
+    }
+
+    /// let 文などの定義
+    /// コマンドの定義はコマンド名を、それ以外は最初の pattern や変数を名前にする
+    fn definition(&self, cst: &Cst, detail: &str) -> DocumentSymbol {
+        let command = find(
+            cst,
+            &[
+                Rule::inline_cmd_name,
+                Rule::block_cmd_name,
+                Rule::math_cmd_name,
+            ],
+        );
+        let name = command.or_else(|| cst.inner.first()).unwrap_or(cst);
+        let kind = if command.is_some() || find(cst, &[Rule::arg]).is_some() {
+            SymbolKind::FUNCTION
+        } else {
+            SymbolKind::VARIABLE
+        };
+        self.leaf(cst, name, kind, detail)
+    }
+
+    /// type 定義
+    /// variant を持つ場合は variant を children にする
+    fn type_definition(&self, cst: &Cst) -> DocumentSymbol {
+        let name = find(cst, &[Rule::type_name]).unwrap_or(cst);
+        let variants = cst
+            .inner
+            .iter()
+            .filter(|inner| inner.rule == Rule::type_variant)
+            .map(|variant| {
+                let name = find(variant, &[Rule::variant_name]).unwrap_or(variant);
+                self.leaf(variant, name, SymbolKind::ENUM_MEMBER, RESERVED_WORD.of)
+            })
+            .collect::<Vec<_>>();
+        if variants.is_empty() {
+            self.leaf(cst, name, SymbolKind::STRUCT, RESERVED_WORD.type_stmt)
+        } else {
+            let mut symbol = self.leaf(cst, name, SymbolKind::ENUM, RESERVED_WORD.type_stmt);
+            symbol.children = Some(variants);
+            symbol
+        }
+    }
+
+    /// `+section{title}< ... >` などの見出し
+    /// 最初の `{ }` の引数を名前にする
+    fn section(&self, cst: &Cst) -> Option<DocumentSymbol> {
+        let command = find(cst, &[Rule::block_cmd_name])?;
+        let command_name = self.get_text(command.span).trim();
+        if !SECTION_COMMANDS.contains(&command_name) {
+            return None;
+        }
+        let title = cst.inner.iter().find(|inner| {
+            inner.rule == Rule::cmd_text_arg && self.get_text(inner.span).starts_with('{')
+        });
+        let mut symbol = self.symbol(
+            cst,
+            title.unwrap_or(command),
+            SymbolKind::STRING,
+            command_name,
+        );
+        if let Some(title) = title {
+            let text = self.get_text(title.span);
+            symbol.name = normalize(&text[1..text.len() - 1]);
+        }
+        if symbol.name.is_empty() {
+            symbol.name = command_name.to_string();
+        }
+        Some(symbol)
+    }
+
+    fn get_text(&self, span: Span) -> &'a str {
+        &self.text[span.start..span.end]
+    }
+
+    /// 前後の空白を除いた span の範囲
+    fn range(&self, span: Span) -> Range {
+        let text = self.get_text(span);
+        let start = span.start + (text.len() - text.trim_start().len());
+        let end = span.start + text.trim_end().len();
+        self.encoding.range(self.text, start..end.max(start))
+    }
+}
+
+/// `rules` のいずれかの規則の最初の子要素
+fn find<'a>(cst: &'a Cst, rules: &[Rule]) -> Option<&'a Cst> {
+    cst.inner.iter().find(|inner| rules.contains(&inner.rule))
+}
+
+/// 改行や連続する空白を1つの空白にまとめる
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
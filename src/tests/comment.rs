@@ -1,4 +1,5 @@
-use super::test_tmpl;
+use super::{test_tmpl, test_tmpl_with_option};
+use crate::FormatOptions;
 
 #[test]
 fn test_comment1() {
@@ -37,8 +38,7 @@ document(|title = {hello}|)'<+p{hello% comment
 % comment
 document(|title = { hello }|)'<
     +p {
-        hello
-        % comment
+        hello% comment
     }
 >
 "#;
@@ -65,8 +65,7 @@ document(|title = {hello}|)'<+p{hello% comment
 % comment
 document(|title = { hello }|)'<
     +p {
-        hello
-        % comment
+        hello% comment
         \listing {
             * item1
             * item2
@@ -148,8 +147,7 @@ document(|title = { hello }|)'<
     +p % comment
     {
         hello
-        \\
-        % comment
+        \\% comment
     }
 >
 "#;
@@ -203,3 +201,170 @@ document(|title = { hello }|)'<
 "#;
     test_tmpl(text, expect)
 }
+
+#[test]
+fn test_comment_in_string_literal() {
+    let text = r#"let s = `50%`
+let t = ``a`%b`` ^ `c%`
+in
+document(||)'<+p{\code(`100%`); 100\% done}>"#;
+
+    let expect = r#"let s = `50%`
+let t = ``a`%b`` ^ `c%`
+in
+
+document(||)'<
+    +p { \code (`100%`); 100\% done }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_comment_trailing() {
+    let text = r#"let x = 1 % note
+let y = 2
+  % leading
+let z = 3
+in
+document(||)'<+p{hello}   % after block
+>"#;
+
+    let expect = r#"let x = 1 % note
+let y = 2
+% leading
+let z = 3
+in
+
+document(||)'<
+    +p { hello } % after block
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_comment_trailing_align() {
+    let text = r#"let x = 1 % one
+let long-name = 2 % two
+let z = 0
+let y = 3 % three
+in
+document(||)'<+p{hello % text
+}>"#;
+
+    let expect = r#"let x = 1         % one
+let long-name = 2 % two
+let z = 0
+let y = 3 % three
+in
+
+document(||)'<
+    +p {
+        hello % text
+    }
+>
+"#;
+    let option = FormatOptions {
+        align_trailing_comments: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_comment_align_wide() {
+    let text = r#"let t = {あいう} % wide
+let x = 1 % one
+in
+document(||)'<+p{hello}>"#;
+
+    let expect = r#"let t = { あいう } % wide
+let x = 1          % one
+in
+
+document(||)'<
+    +p { hello }
+>
+"#;
+    let option = FormatOptions {
+        align_trailing_comments: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_comment_control_char() {
+    // 印を挟む私用領域の文字や制御文字を含んでいても、コメントと整形しない範囲は元のまま出力する
+    let text = "let s = 1 % a\u{E000}b\u{2}\n% satysfi-fmt: off\nlet t   =   2   % c\u{E000}\u{E001}\n% satysfi-fmt: on\nlet u   =   3 % d\u{0}\nin\ndocument(||)'<+p{hello}>";
+    let expect = "let s = 1 % a\u{E000}b\u{2}\n% satysfi-fmt: off\nlet t   =   2   % c\u{E000}\u{E001}\n% satysfi-fmt: on\nlet u = 3 % d\u{0}\nin\n\ndocument(||)'<\n    +p { hello }\n>\n";
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_comment_format_off() {
+    let text = r#"let x   =   1
+% satysfi-fmt: off
+let table = [
+  1;   2;
+  30;  4;
+]
+% satysfi-fmt: on
+let y   =   2
+in
+document(||)'<+p{hello}>"#;
+
+    let expect = r#"let x = 1
+% satysfi-fmt: off
+let table = [
+  1;   2;
+  30;  4;
+]
+% satysfi-fmt: on
+let y = 2
+in
+
+document(||)'<
+    +p { hello }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_comment_format_skip() {
+    let text = r#"% satysfi-fmt: skip
+let x   =   [1;2]
+let y   =   [1;2]
+in
+document(||)'<
+% satysfi-fmt: skip
++p{hello}
++p{hello}>"#;
+
+    let expect = r#"% satysfi-fmt: skip
+let x   =   [1;2]
+let y = [1; 2]
+in
+
+document(||)'<
+    % satysfi-fmt: skip
+    +p{hello}
+    +p { hello }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_comment_format_skip_verbatim() {
+    // skip した要素の中は行末の空白の除去やコメントの列揃えもしない
+    let text = "% satysfi-fmt: skip\nlet x = [\n  1;   % one  \n  22;  % two\n]\nlet y   =   2 % three\nlet zz = 3 % four\nin\ndocument(||)'<+p{hello}>";
+    let expect = "% satysfi-fmt: skip\nlet x = [\n  1;   % one  \n  22;  % two\n]\nlet y = 2  % three\nlet zz = 3 % four\nin\n\ndocument(||)'<\n    +p { hello }\n>\n";
+    let option = FormatOptions {
+        align_trailing_comments: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
@@ -105,10 +105,7 @@ document(|title = {hello}|)'<+p{hello world}+p {${ax^2 + bx + c = 0}}>"#;
 fn test7() {
     let text = r#"
 document(|title = {hello}; author = {author};|)'<>"#;
-    let expect = r#"document(|
-    title = { hello };
-    author = { author };
-|)'<>
+    let expect = r#"document(|title = { hello }; author = { author }|)'<>
 "#;
     test_tmpl(text, expect);
 }
@@ -140,12 +137,7 @@ document(|
     let expect = r#"@require: stdja
 @require: itemize
 
-document(|
-    author = { author };
-    show-title = false;
-    show-toc = true;
-    title = { title };
-|)'<
+document(|author = { author }; show-title = false; show-toc = true; title = { title }|)'<
     +section { section } <
         +p {
             \listing {
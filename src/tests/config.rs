@@ -0,0 +1,57 @@
+use crate::{Config, FormatOptions};
+use std::fs;
+
+#[test]
+fn test_config_parse() {
+    let config: Config = "indent-space = 2".parse().unwrap();
+    assert_eq!(
+        config,
+        Config {
+            indent_space: Some(2),
+            ..Default::default()
+        }
+    );
+    assert!("indent-size = 2".parse::<Config>().is_err());
+}
+
+#[test]
+fn test_config_merge() {
+    let file: Config = "indent-space = 2\ninsert-final-newline = false"
+        .parse()
+        .unwrap();
+    let cli = Config {
+        indent_space: Some(8),
+        ..Default::default()
+    };
+    let option = file.merge(cli).format_options();
+    assert_eq!(option.indent_width, 8);
+    assert!(!option.insert_final_newline);
+
+    assert_eq!(Config::default().format_options(), FormatOptions::default());
+}
+
+#[test]
+fn test_config_verbatim_commands() {
+    let config: Config = r#"verbatim-commands = ["+listing", "\\verb"]"#.parse().unwrap();
+    assert_eq!(
+        config.format_options().verbatim_commands,
+        vec!["+listing".to_string(), "\\verb".to_string()]
+    );
+    assert!(FormatOptions::default()
+        .verbatim_commands
+        .contains(&"\\code".to_string()));
+}
+
+#[test]
+fn test_config_find() {
+    let root = std::env::temp_dir().join(format!("satysfi-fmt-config-{}", std::process::id()));
+    let dir = root.join("src").join("chapter");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(root.join(".satysfi-fmt.toml"), "indent-space = 2\n").unwrap();
+
+    let (config, path) = Config::discover(&dir).unwrap();
+    assert_eq!(path, Some(root.join(".satysfi-fmt.toml")));
+    assert_eq!(config.indent_space, Some(2));
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,82 @@
+use super::test_tmpl_with_option;
+use crate::{CommandSpace, FormatOptions};
+
+fn option(command_space: CommandSpace) -> FormatOptions {
+    FormatOptions {
+        command_space,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_cspace_never() {
+    let text = r#"document(||)'<+section{hello}<+p{hello\bold{abc}def}>>"#;
+
+    let expect = r#"document(||)'<
+    +section{ hello }<
+        +p{ hello\bold{ abc }def }
+    >
+>
+"#;
+    test_tmpl_with_option(text, expect, option(CommandSpace::Never));
+}
+
+#[test]
+fn test_cspace_always() {
+    let text = r#"document(||)'<+section{hello}<+p{hello\bold{abc}def}>>"#;
+
+    let expect = r#"document(||)'<
+    +section { hello } <
+        +p { hello\bold { abc }def }
+    >
+>
+"#;
+    test_tmpl_with_option(text, expect, option(CommandSpace::Always));
+}
+
+#[test]
+fn test_cspace_expr_arg() {
+    let text = r#"document(||)'<+p{\href(`https://example.com`){link}}>"#;
+
+    let expect = r#"document(||)'<
+    +p{ \href(`https://example.com`){ link } }
+>
+"#;
+    test_tmpl_with_option(text, expect, option(CommandSpace::Never));
+}
+
+#[test]
+fn test_cspace_math() {
+    let text = r#"document(||)'<+p{${\paren{1 + a} \sqrt{x}}}>"#;
+
+    let expect = r#"document(||)'<
+    +p { ${\paren {1 + a} \sqrt {x}} }
+>
+"#;
+    test_tmpl_with_option(text, expect, option(CommandSpace::Always));
+
+    let expect = r#"document(||)'<
+    +p{ ${\paren{1 + a} \sqrt{x}} }
+>
+"#;
+    test_tmpl_with_option(text, expect, option(CommandSpace::Never));
+}
+
+#[test]
+fn test_cspace_nested_math_cmd() {
+    let text = r#"document(||)'<+p{${\frac{\sqrt{x}}{y}}}>"#;
+
+    let expect = r#"document(||)'<
+    +p { ${\frac {\sqrt {x}} {y}} }
+>
+"#;
+    test_tmpl_with_option(text, expect, option(CommandSpace::Always));
+
+    let text = r#"document(||)'<+p{${\frac {\sqrt {x}} {y}}}>"#;
+
+    let expect = r#"document(||)'<
+    +p{ ${\frac{\sqrt{x}}{y}} }
+>
+"#;
+    test_tmpl_with_option(text, expect, option(CommandSpace::Never));
+}
@@ -0,0 +1,79 @@
+use crate::{diff::text_edits, unified_diff, PositionEncoding};
+use lspower::lsp::{Position, Range, TextEdit};
+
+#[test]
+fn test_unified_diff() {
+    let original = "document(||)'<\n+p{hello}\n>\n";
+    let formatted = "document(||)'<\n    +p { hello }\n>\n";
+    let expect = r#"--- doc.saty
++++ doc.saty
+@@ -1,3 +1,3 @@
+ document(||)'<
+-+p{hello}
++    +p { hello }
+ >
+"#;
+    assert_eq!(unified_diff(original, formatted, "doc.saty"), expect);
+}
+
+#[test]
+fn test_unified_diff_no_change() {
+    let text = "document(||)'<\n    +p { hello }\n>\n";
+    assert_eq!(unified_diff(text, text, "doc.saty"), "");
+}
+
+#[test]
+fn test_text_edits() {
+    let original = "let x = 1\nlet   y = 2\nlet z = 3\n";
+    let formatted = "let x = 1\nlet y = 2\nlet z = 3\n";
+    let edits = text_edits(original, formatted, PositionEncoding::Utf16);
+    assert_eq!(
+        edits,
+        vec![TextEdit {
+            range: Range {
+                start: Position {
+                    line: 1,
+                    character: 4
+                },
+                end: Position {
+                    line: 1,
+                    character: 6
+                },
+            },
+            new_text: String::new(),
+        }]
+    );
+}
+
+#[test]
+fn test_text_edits_multiple_hunks() {
+    let original = "document(||)'<\n+p{hello}\n    +p { world }\n+p{!}\n>";
+    let formatted = "document(||)'<\n    +p { hello }\n    +p { world }\n    +p { ! }\n>\n";
+    let edits = text_edits(original, formatted, PositionEncoding::Utf16);
+    assert_eq!(edits.len(), 2);
+    assert_eq!(apply(original, &edits), formatted);
+}
+
+#[test]
+fn test_text_edits_no_change() {
+    let text = "let x = 1\n";
+    assert!(text_edits(text, text, PositionEncoding::Utf16).is_empty());
+}
+
+/// TextEdit を後ろから順に適用する
+fn apply(text: &str, edits: &[TextEdit]) -> String {
+    let offset = |position: Position| {
+        let line_start: usize = text
+            .split_inclusive('\n')
+            .take(position.line as usize)
+            .map(str::len)
+            .sum();
+        line_start + position.character as usize
+    };
+    let mut text = text.to_string();
+    for edit in edits.iter().rev() {
+        let range = offset(edit.range.start)..offset(edit.range.end);
+        text.replace_range(range, &edit.new_text);
+    }
+    text
+}
@@ -0,0 +1,56 @@
+use crate::{format, try_format, FormatError, FormatOptions, PositionEncoding};
+use lspower::lsp::Position;
+
+#[test]
+fn test_parse_error() {
+    let text = r#"@require: stdja
+
+document(||)'<
+    +p{ hello }}
+>
+"#;
+    let err = try_format(text, FormatOptions::default()).unwrap_err();
+    assert_eq!(err.line, 4);
+    assert_eq!(err.column, 16);
+    assert_eq!(err.snippet, "    +p{ hello }}");
+    assert!(!err.expected.is_empty());
+
+    // format は元の文字列をそのまま返す
+    assert_eq!(format(text, FormatOptions::default()), text);
+}
+
+#[test]
+fn test_parse_error_diagnostic() {
+    let text = "let x = 1\nlet あい = }\n";
+    let err = FormatError {
+        line: 2,
+        column: 10,
+        expected: vec!["expr".to_string()],
+        snippet: "let あい = }".to_string(),
+    };
+    let diagnostic = err.to_diagnostic(text, PositionEncoding::Utf16);
+    assert_eq!(
+        diagnostic.range.start,
+        Position {
+            line: 1,
+            character: 9
+        }
+    );
+    assert_eq!(
+        diagnostic.range.end,
+        Position {
+            line: 1,
+            character: 10
+        }
+    );
+    assert_eq!(diagnostic.message, "parse error: expected expr");
+
+    let diagnostic = err.to_diagnostic(text, PositionEncoding::Utf8);
+    assert_eq!(
+        diagnostic.range.start,
+        Position {
+            line: 1,
+            character: 13
+        }
+    );
+}
@@ -0,0 +1,57 @@
+use crate::folding_ranges;
+use lspower::lsp::FoldingRangeKind;
+
+/// 折りたたみ範囲を (開始行, 終了行, コメントかどうか) で並べる
+fn test_folding(input: &str, expect: Vec<(u32, u32, bool)>) {
+    let ranges = folding_ranges(input)
+        .into_iter()
+        .map(|range| {
+            let comment = range.kind == Some(FoldingRangeKind::Comment);
+            (range.start_line, range.end_line, comment)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, expect);
+}
+
+#[test]
+fn test_folding_blocks() {
+    let text = r#"document(|
+    title = { title };
+|)'<
+    +section{ section }<
+        +p{
+            hello
+        }
+    >
+>
+"#;
+    let expect = vec![(0, 1, false), (2, 7, false), (3, 6, false), (4, 5, false)];
+    test_folding(text, expect);
+}
+
+#[test]
+fn test_folding_module() {
+    let text = r#"module Foo = struct
+    let x = 1
+    let y = [
+        1;
+        2;
+    ]
+end
+"#;
+    let expect = vec![(0, 5, false), (2, 4, false)];
+    test_folding(text, expect);
+}
+
+#[test]
+fn test_folding_comments() {
+    let text = r#"% comment 1
+% comment 2
+% comment 3
+let x = 1 % trailing
+% single
+let y = 2
+"#;
+    let expect = vec![(0, 2, true)];
+    test_folding(text, expect);
+}
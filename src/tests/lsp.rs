@@ -0,0 +1,287 @@
+use crate::{lsp::Backend, FormatOptions, PositionEncoding};
+use futures::StreamExt;
+use lspower::{jsonrpc::Incoming, lsp::TextEdit, LspService};
+use serde_json::{json, Value};
+use std::sync::{mpsc, Arc, Mutex};
+use tower_service::Service;
+
+const URI: &str = "file:///test.saty";
+
+fn run<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// in-process の client としてメッセージを送り、レスポンスの JSON を返す
+async fn send(service: &mut LspService, message: Value) -> Option<Value> {
+    let message: Incoming = serde_json::from_value(message).unwrap();
+    service
+        .call(message)
+        .await
+        .unwrap()
+        .map(|response| serde_json::to_value(response).unwrap())
+}
+
+async fn request(service: &mut LspService, method: &str, params: Value) -> Value {
+    let response = send(
+        service,
+        json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": 1 }),
+    )
+    .await
+    .unwrap();
+    response["result"].clone()
+}
+
+async fn notify(service: &mut LspService, method: &str, params: Value) {
+    let response = send(
+        service,
+        json!({ "jsonrpc": "2.0", "method": method, "params": params }),
+    )
+    .await;
+    assert_eq!(response, None);
+}
+
+async fn initialize(service: &mut LspService) -> Value {
+    request(service, "initialize", json!({ "capabilities": {} })).await
+}
+
+async fn open(service: &mut LspService, text: &str) {
+    let params = json!({
+        "textDocument": { "uri": URI, "languageId": "satysfi", "version": 1, "text": text }
+    });
+    notify(service, "textDocument/didOpen", params).await;
+}
+
+async fn format(service: &mut LspService) -> Value {
+    let params = json!({
+        "textDocument": { "uri": URI },
+        "options": { "tabSize": 4, "insertSpaces": true }
+    });
+    request(service, "textDocument/formatting", params).await
+}
+
+/// レスポンスの TextEdit を text に適用した結果
+fn apply_edits(text: &str, edits: &Value) -> String {
+    let edits: Vec<TextEdit> = serde_json::from_value(edits.clone()).unwrap();
+    let mut output = text.to_string();
+    // 後ろの TextEdit から適用して、前の TextEdit の位置をずらさない
+    for edit in edits.iter().rev() {
+        let start = PositionEncoding::Utf16.offset(text, edit.range.start);
+        let end = PositionEncoding::Utf16.offset(text, edit.range.end);
+        output.replace_range(start..end, &edit.new_text);
+    }
+    output
+}
+
+#[test]
+fn test_lsp_initialize() {
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        let result = initialize(&mut service).await;
+        let sync = &result["capabilities"]["textDocumentSync"];
+        assert_eq!(sync["change"], json!(1));
+        assert_eq!(sync["willSaveWaitUntil"], json!(true));
+        assert_eq!(
+            result["capabilities"]["documentFormattingProvider"],
+            json!(true)
+        );
+    });
+}
+
+#[test]
+fn test_lsp_formatting() {
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        initialize(&mut service).await;
+        let text = "let x = 1\nlet y = 2";
+        open(&mut service, text).await;
+        let edits = format(&mut service).await;
+        // 変更のあった箇所だけを TextEdit にする
+        let expect = json!([{
+            "range": {
+                "start": { "line": 1, "character": 9 },
+                "end": { "line": 1, "character": 9 }
+            },
+            "newText": "\n"
+        }]);
+        assert_eq!(edits, expect);
+        assert_eq!(apply_edits(text, &edits), "let x = 1\nlet y = 2\n");
+    });
+}
+
+#[test]
+fn test_lsp_document_sync() {
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        initialize(&mut service).await;
+        open(&mut service, "let x = 1").await;
+
+        let text = "let   y =   2";
+        let params = json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": text }]
+        });
+        notify(&mut service, "textDocument/didChange", params).await;
+        let edits = format(&mut service).await;
+        assert_eq!(edits.as_array().unwrap().len(), 1);
+        assert_eq!(
+            edits[0]["range"]["start"],
+            json!({ "line": 0, "character": 4 })
+        );
+        assert_eq!(apply_edits(text, &edits), "let y = 2\n");
+
+        let params = json!({ "textDocument": { "uri": URI } });
+        notify(&mut service, "textDocument/didClose", params).await;
+        assert_eq!(format(&mut service).await, Value::Null);
+    });
+}
+
+#[test]
+fn test_lsp_offset_encoding() {
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        let params = json!({ "capabilities": { "offsetEncoding": ["utf-32", "utf-8"] } });
+        let result = request(&mut service, "initialize", params).await;
+        assert_eq!(result["offsetEncoding"], json!("utf-32"));
+    });
+
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        let result = initialize(&mut service).await;
+        assert_eq!(result.get("offsetEncoding"), None);
+    });
+}
+
+#[test]
+fn test_lsp_range_formatting() {
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        initialize(&mut service).await;
+        open(&mut service, "let x =1\nlet   y   = 2\n").await;
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "range": {
+                "start": { "line": 1, "character": 0 },
+                "end": { "line": 1, "character": 3 }
+            },
+            "options": { "tabSize": 4, "insertSpaces": true }
+        });
+        let edits = request(&mut service, "textDocument/rangeFormatting", params).await;
+        assert_eq!(edits.as_array().unwrap().len(), 1);
+        assert_eq!(edits[0]["range"]["start"]["line"], json!(1));
+    });
+}
+
+#[test]
+fn test_lsp_diagnostics() {
+    let (mut service, mut messages) = LspService::new(Backend::new);
+    run(async {
+        initialize(&mut service).await;
+
+        open(&mut service, "document(||)'<\n    +p{ hello }}\n>").await;
+        let message = serde_json::to_value(messages.next().await.unwrap()).unwrap();
+        assert_eq!(message["method"], json!("textDocument/publishDiagnostics"));
+        let diagnostics = message["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], json!(1));
+
+        // parse できるようになったら diagnostics を消す
+        let params = json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "document(||)'<\n    +p{ hello }\n>" }]
+        });
+        notify(&mut service, "textDocument/didChange", params).await;
+        let message = serde_json::to_value(messages.next().await.unwrap()).unwrap();
+        assert_eq!(message["params"]["diagnostics"], json!([]));
+    });
+}
+
+#[test]
+fn test_lsp_will_save_wait_until() {
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        initialize(&mut service).await;
+        let text = "let   x = 1";
+        open(&mut service, text).await;
+        let params = json!({ "textDocument": { "uri": URI }, "reason": 1 });
+        let edits = request(&mut service, "textDocument/willSaveWaitUntil", params).await;
+        let expect = json!([{
+            "range": {
+                "start": { "line": 0, "character": 4 },
+                "end": { "line": 0, "character": 11 }
+            },
+            "newText": "x = 1\n"
+        }]);
+        assert_eq!(edits, expect);
+        assert_eq!(apply_edits(text, &edits), "let x = 1\n");
+    });
+}
+
+#[test]
+fn test_lsp_will_save_wait_until_timeout() {
+    // release を drop するまで format が終わらないようにして、必ず時間切れにする
+    let (release, wait) = mpsc::channel::<()>();
+    let wait = Mutex::new(wait);
+    let formatting = move |_: &str, _: FormatOptions, _: PositionEncoding| {
+        let _ = wait.lock().unwrap().recv();
+        Vec::new()
+    };
+    let (mut service, mut messages) =
+        LspService::new(|client| Backend::with_formatting(client, Arc::new(formatting)));
+    run(async {
+        let params = json!({
+            "capabilities": {},
+            "initializationOptions": { "willSaveWaitUntilTimeout": 10 }
+        });
+        request(&mut service, "initialize", params).await;
+        open(&mut service, "let x = 1\n").await;
+        // didOpen の diagnostics
+        messages.next().await;
+
+        let params = json!({ "textDocument": { "uri": URI }, "reason": 1 });
+        let edits = request(
+            &mut service,
+            "textDocument/willSaveWaitUntil",
+            params.clone(),
+        )
+        .await;
+        assert_eq!(edits, Value::Null);
+        let message = serde_json::to_value(messages.next().await.unwrap()).unwrap();
+        assert_eq!(message["method"], json!("window/logMessage"));
+        assert_eq!(message["params"]["type"], json!(2));
+
+        // 時間切れになった format が終わるまで、同じドキュメントの format は始めない
+        let edits = request(&mut service, "textDocument/willSaveWaitUntil", params).await;
+        assert_eq!(edits, Value::Null);
+        let message = serde_json::to_value(messages.next().await.unwrap()).unwrap();
+        assert!(message["params"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("still running"));
+        drop(release);
+    });
+}
+
+#[test]
+fn test_lsp_will_save_wait_until_options() {
+    let (mut service, _) = LspService::new(Backend::new);
+    run(async {
+        initialize(&mut service).await;
+        let text = "document(||)'<\n  +p { hello }\n>\n";
+        open(&mut service, text).await;
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "options": { "tabSize": 2, "insertSpaces": true }
+        });
+        let edits = request(&mut service, "textDocument/formatting", params).await;
+        assert_eq!(edits, json!([]));
+
+        // 保存時も最後に受け取った FormattingOptions で format する
+        let params = json!({ "textDocument": { "uri": URI }, "reason": 1 });
+        let edits = request(&mut service, "textDocument/willSaveWaitUntil", params).await;
+        assert_eq!(edits, json!([]));
+    });
+}
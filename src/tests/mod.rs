@@ -1,21 +1,37 @@
-use crate::format;
-use lspower::lsp::FormattingOptions;
+use crate::{format, FormatOptions};
 
 mod comment;
 mod common;
+mod config;
+mod cspace;
 mod ctrl_stmt;
+mod diff;
+mod error;
+mod folding;
 mod horizontal_single;
 mod let_block;
+mod lsp;
 mod math;
 mod module;
+mod on_type;
+mod position;
+mod range;
+mod semantic;
 mod space;
+mod symbol;
+mod tab;
+mod verbatim;
+mod width;
 
 fn test_tmpl(input: &str, expect: &str) {
-    let option = FormattingOptions {
-        tab_size: 4,
-        insert_spaces: true,
+    let option = FormatOptions {
+        indent_width: 4,
         ..Default::default()
     };
+    test_tmpl_with_option(input, expect, option);
+}
+
+fn test_tmpl_with_option(input: &str, expect: &str, option: FormatOptions) {
     let output = format(input, option);
     assert_eq!(output, expect);
 }
@@ -0,0 +1,46 @@
+use crate::{format_on_type, FormatOptions};
+
+/// `input` の末尾で文字を入力したとして format する
+fn test_on_type(input: &str, expect: &str) {
+    let output = format_on_type(input, input.len(), FormatOptions::default()).unwrap();
+    assert_eq!(output, expect);
+}
+
+#[test]
+fn test_on_type_cmd_text_arg() {
+    let text = r#"document(||)'<
+    +p{
+  hello
+   world
+  }"#;
+    let expect = r#"document(||)'<
+    +p{
+        hello
+        world
+    }"#;
+    let rest = "\n>";
+    let input = format!("{text}{rest}");
+    let output = format_on_type(&input, text.len(), FormatOptions::default()).unwrap();
+    assert_eq!(output, format!("{expect}{rest}"));
+}
+
+#[test]
+fn test_on_type_record() {
+    let text = "let r = (|\n  a = 1;\n    b = 2;\n      |)";
+    let expect = "let r = (|a = 1; b = 2|)";
+    test_on_type(text, expect);
+}
+
+#[test]
+fn test_on_type_newline() {
+    let text = "let r = (|\n  a = 1;\n    b = 2;\n      |)\n";
+    let expect = "let r = (|a = 1; b = 2|)\n";
+    test_on_type(text, expect);
+}
+
+#[test]
+fn test_on_type_not_closed() {
+    // 閉じ括弧の直後でなければ何もしない
+    let text = "let r = (|\n  a = 1;\n    b = 2;\n      |) + 1";
+    test_on_type(text, text);
+}
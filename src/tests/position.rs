@@ -0,0 +1,61 @@
+use crate::{diff::text_edits, PositionEncoding};
+use lspower::lsp::Position;
+
+const TEXT: &str = "+p { あいう𝑥 }\n+p{ 日本語 }";
+
+fn pos(line: u32, character: u32) -> Position {
+    Position { line, character }
+}
+
+#[test]
+fn test_position() {
+    // "𝑥" の後ろ: UTF-8 で 5 + 9 + 4, UTF-16 で 5 + 3 + 2, UTF-32 で 5 + 3 + 1
+    let offset = TEXT.find(" }").unwrap();
+    assert_eq!(PositionEncoding::Utf8.position(TEXT, offset), pos(0, 18));
+    assert_eq!(PositionEncoding::Utf16.position(TEXT, offset), pos(0, 10));
+    assert_eq!(PositionEncoding::Utf32.position(TEXT, offset), pos(0, 9));
+
+    let offset = TEXT.len();
+    assert_eq!(PositionEncoding::Utf16.position(TEXT, offset), pos(1, 9));
+}
+
+#[test]
+fn test_offset() {
+    let offset = TEXT.find(" }").unwrap();
+    assert_eq!(PositionEncoding::Utf8.offset(TEXT, pos(0, 18)), offset);
+    assert_eq!(PositionEncoding::Utf16.offset(TEXT, pos(0, 10)), offset);
+    assert_eq!(PositionEncoding::Utf32.offset(TEXT, pos(0, 9)), offset);
+    // 行末や文書の末尾を超える位置は丸める
+    assert_eq!(
+        PositionEncoding::Utf16.offset(TEXT, pos(0, 100)),
+        TEXT.find('\n').unwrap()
+    );
+    assert_eq!(PositionEncoding::Utf16.offset(TEXT, pos(5, 0)), TEXT.len());
+}
+
+#[test]
+fn test_negotiate() {
+    assert_eq!(
+        PositionEncoding::negotiate(&["utf-8", "utf-16"]),
+        PositionEncoding::Utf8
+    );
+    assert_eq!(
+        PositionEncoding::negotiate(&["utf-7", "utf-32"]),
+        PositionEncoding::Utf32
+    );
+    assert_eq!(
+        PositionEncoding::negotiate::<&str>(&[]),
+        PositionEncoding::Utf16
+    );
+}
+
+#[test]
+fn test_text_edits_utf16() {
+    let original = "+p{ 日本語 }\n+p{ 日本語  }\n";
+    let formatted = "+p{ 日本語 }\n+p{ 日本語 }\n";
+    let edits = text_edits(original, formatted, PositionEncoding::Utf16);
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].range.start, pos(1, 8));
+    assert_eq!(edits[0].range.end, pos(1, 9));
+    assert_eq!(edits[0].new_text, "");
+}
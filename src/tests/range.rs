@@ -0,0 +1,65 @@
+use crate::{format_range, FormatOptions};
+
+/// `marker` の位置を選択範囲として format する
+fn test_range(input: &str, marker: &str, expect: &str) {
+    let start = input.find(marker).unwrap();
+    let range = start..start + marker.len();
+    let output = format_range(input, range, FormatOptions::default()).unwrap();
+    assert_eq!(output, expect);
+}
+
+#[test]
+fn test_range_block_cmd() {
+    let text = r#"document(||)'<
+    +p{hello}
+    +p{world}
+>"#;
+    let expect = r#"document(||)'<
+    +p{hello}
+    +p { world }
+>"#;
+    test_range(text, "world", expect);
+}
+
+#[test]
+fn test_range_nested_depth() {
+    let text = r#"document(||)'<
+    +section{section}<
+        +p{
+        hello
+                world
+            }
+    >
+>"#;
+    let expect = r#"document(||)'<
+    +section{section}<
+        +p {
+            hello
+            world
+        }
+    >
+>"#;
+    test_range(text, "hello", expect);
+}
+
+#[test]
+fn test_range_vertical() {
+    let text = r#"document(||)'<
+    +p{a}
+    +p{b}
+    +p{c}
+>"#;
+    let expect = r#"document(||)'<
+    +p{a}
+    +p { b }
+    +p { c }
+>"#;
+    test_range(text, "b}\n    +p{c", expect);
+}
+
+#[test]
+fn test_range_let_stmt() {
+    let text = "let x =1\nlet   y   = 2\n";
+    let expect = "let x =1\nlet y = 2\n";
+    test_range(text, "y", expect);
+}
@@ -0,0 +1,63 @@
+use crate::{semantic_tokens, PositionEncoding, TOKEN_TYPES};
+
+/// 相対位置の token を (行, 列, 長さ, 種類) に戻す
+fn tokens(input: &str) -> Vec<(u32, u32, u32, String)> {
+    let mut line = 0;
+    let mut character = 0;
+    semantic_tokens(input, PositionEncoding::Utf16)
+        .into_iter()
+        .map(|token| {
+            if token.delta_line > 0 {
+                character = 0;
+            }
+            line += token.delta_line;
+            character += token.delta_start;
+            let token_type = TOKEN_TYPES[token.token_type as usize].as_str().to_string();
+            (line, character, token.length, token_type)
+        })
+        .collect()
+}
+
+fn token(line: u32, character: u32, length: u32, token_type: &str) -> (u32, u32, u32, String) {
+    (line, character, length, token_type.to_string())
+}
+
+#[test]
+fn test_semantic_tokens() {
+    let text = r#"% コメント
+let-inline ctx \bold it = it
+type t = A | B of int
+let s = `let` ^ `in`
+let w = 10pt
+"#;
+    let tokens = tokens(text);
+    let expect = vec![
+        token(0, 0, 6, "comment"),
+        token(1, 0, 10, "keyword"),
+        token(1, 15, 5, "macro"),
+        token(2, 0, 4, "keyword"),
+        token(2, 5, 1, "type"),
+        token(2, 9, 1, "enumMember"),
+        token(2, 13, 1, "enumMember"),
+        token(2, 15, 2, "keyword"),
+        token(3, 8, 5, "string"),
+        token(3, 16, 4, "string"),
+        token(4, 8, 4, "number"),
+    ];
+    for token in expect {
+        assert!(tokens.contains(&token), "{token:?} not found in {tokens:?}");
+    }
+    // 文字列の中の予約語は keyword にしない
+    assert!(!tokens.contains(&token(3, 9, 3, "keyword")));
+}
+
+#[test]
+fn test_semantic_tokens_text() {
+    // 地の文の中の単語は keyword にしない
+    let text = "document(||)'<\n    +p{ let it be }\n>\n";
+    let tokens = tokens(text);
+    assert!(tokens.contains(&token(1, 4, 2, "macro")));
+    assert!(tokens
+        .iter()
+        .all(|(line, _, _, token_type)| *line != 1 || token_type != "keyword"));
+}
@@ -44,14 +44,8 @@ fn test_space3() {
     +p % comment
     { hello\bold { abc }def }
     +align [
-        [
-            ${};
-            ${\( 1 + a \) \( 1 + a^2 \) \( 1 + a^{2^2} \)};
-        ];
-        [
-            ${};
-            ${\paren{1 + a} \paren{1 + a^2} \paren{1 + a^{2^2}}};
-        ];
+        [${}; ${\( 1 + a \) \( 1 + a^2 \) \( 1 + a^{2^2} \)}];
+        [${}; ${\paren{1 + a} \paren{1 + a^2} \paren{1 + a^{2^2}}}];
     ];
 >
 "#;
@@ -0,0 +1,73 @@
+use crate::{document_symbols, PositionEncoding};
+use lspower::lsp::{DocumentSymbol, SymbolKind};
+
+/// symbol の名前と種類を階層ごとに並べる
+fn outline(symbols: &[DocumentSymbol]) -> Vec<(String, SymbolKind, usize)> {
+    fn walk(
+        symbols: &[DocumentSymbol],
+        depth: usize,
+        output: &mut Vec<(String, SymbolKind, usize)>,
+    ) {
+        for symbol in symbols {
+            output.push((symbol.name.clone(), symbol.kind, depth));
+            walk(
+                symbol.children.as_deref().unwrap_or_default(),
+                depth + 1,
+                output,
+            );
+        }
+    }
+    let mut output = Vec::new();
+    walk(symbols, 0, &mut output);
+    output
+}
+
+#[test]
+fn test_symbol_definitions() {
+    let text = r#"@require: stdja
+
+module Foo = struct
+    let x = 1
+    let f y = y
+end
+
+type shape = Circle of length | Rect
+
+let-inline ctx \bold it = it
+"#;
+    let symbols = document_symbols(text, PositionEncoding::Utf16);
+    let expect = vec![
+        ("Foo".to_string(), SymbolKind::MODULE, 0),
+        ("struct".to_string(), SymbolKind::STRUCT, 1),
+        ("x".to_string(), SymbolKind::VARIABLE, 2),
+        ("f".to_string(), SymbolKind::FUNCTION, 2),
+        ("shape".to_string(), SymbolKind::ENUM, 0),
+        ("Circle".to_string(), SymbolKind::ENUM_MEMBER, 1),
+        ("Rect".to_string(), SymbolKind::ENUM_MEMBER, 1),
+        ("\\bold".to_string(), SymbolKind::FUNCTION, 0),
+    ];
+    assert_eq!(outline(&symbols), expect);
+}
+
+#[test]
+fn test_symbol_sections() {
+    let text = r#"document(||)'<
+    +section{はじめに}<
+        +subsection{ 背景 と
+            目的 }<
+            +p{ hello }
+        >
+    >
+    +section{まとめ}<>
+>
+"#;
+    let symbols = document_symbols(text, PositionEncoding::Utf16);
+    let expect = vec![
+        ("はじめに".to_string(), SymbolKind::STRING, 0),
+        ("背景 と 目的".to_string(), SymbolKind::STRING, 1),
+        ("まとめ".to_string(), SymbolKind::STRING, 0),
+    ];
+    assert_eq!(outline(&symbols), expect);
+    assert_eq!(symbols[0].selection_range.start.line, 1);
+    assert_eq!(symbols[0].selection_range.start.character, 12);
+}
@@ -0,0 +1,108 @@
+use super::test_tmpl_with_option;
+use crate::FormatOptions;
+use lspower::lsp::FormattingOptions;
+
+fn option() -> FormatOptions {
+    FormatOptions {
+        use_tabs: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_tab1() {
+    let text = r#"document(||)'<+section{hello}<+p{hello}>>"#;
+
+    let expect = "document(||)'<
+\t+section { hello } <
+\t\t+p { hello }
+\t>
+>
+";
+    test_tmpl_with_option(text, expect, option());
+}
+
+#[test]
+fn test_tab_pipeline() {
+    let text = r#"@require: local
+
+document(|title = {}|)'<
+    +fig-center(vconcat [
+        gap 10pt;
+        textbox { hello } 
+            |> glass-box ?:(align-center, align-center) 100pt 100pt 
+            |> bgcolor (Color.gray 0.8);
+        gap 10pt;
+    ]);
+>"#;
+
+    let expect = "@require: local
+
+document(|title = {}|)'<
+\t+fig-center (vconcat [
+\t\tgap 10pt;
+\t\ttextbox { hello }
+\t\t\t|> glass-box ?:(align-center, align-center) 100pt 100pt
+\t\t\t|> bgcolor (Color.gray 0.8);
+\t\tgap 10pt;
+\t]);
+>
+";
+    test_tmpl_with_option(text, expect, option());
+}
+
+#[test]
+fn test_tab_let_mutable() {
+    let text = r#"let-mutable x <- 1 |> f
+in
+document(||)'<+p{hello}>"#;
+
+    let expect = "let-mutable x <-
+\t1
+\t\t|> f
+in
+
+document(||)'<
+\t+p { hello }
+>
+";
+    test_tmpl_with_option(text, expect, option());
+}
+
+#[test]
+fn test_tab_bullet() {
+    let text = r#"document(||)'<+p{\listing{
+* item1
+** item2
+* item3
+}}>"#;
+
+    let expect = "document(||)'<
+\t+p {
+\t\t\\listing {
+\t\t\t* item1
+\t\t\t\t** item2
+\t\t\t* item3
+\t\t}
+\t}
+>
+";
+    test_tmpl_with_option(text, expect, option());
+}
+
+#[test]
+fn test_tab_formatting_options() {
+    // tab_size が指定されていない場合はスペースのまま
+    assert_eq!(
+        FormatOptions::from(FormattingOptions::default()),
+        FormatOptions::default()
+    );
+
+    let option = FormatOptions::from(FormattingOptions {
+        tab_size: 8,
+        insert_spaces: false,
+        ..Default::default()
+    });
+    assert!(option.use_tabs);
+    assert_eq!(option.indent_width, 8);
+}
@@ -0,0 +1,81 @@
+use super::{test_tmpl, test_tmpl_with_option};
+use crate::FormatOptions;
+
+#[test]
+fn test_verbatim_string() {
+    let text = "let code = ```
+  first   line   
+    second
+```
+in
+document(||)'<+p{#code;}>";
+
+    let expect = "let code = ```
+  first   line   
+    second
+```
+in
+
+document(||)'<
+    +p { #code; }
+>
+";
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_verbatim_command() {
+    let text = r#"document(||)'<
++code(```
+  let x   =   1
+```);
++p{\verb{  a
+    b}}>"#;
+
+    let expect = r#"document(||)'<
+    +code (```
+  let x   =   1
+```);
+    +p {
+        \verb {  a
+    b}
+    }
+>
+"#;
+    let option = FormatOptions {
+        verbatim_commands: vec!["+code".to_string(), "\\verb".to_string()],
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_verbatim_control_char() {
+    // 出力に一時的に入れる印と同じ文字を含んでいても、元の文字のまま出力する
+    let text = "let s = `\u{4}0:1\u{5}\u{E000}` % x\u{4}\nin\ndocument(||)'<+p{hello}>";
+    let expect =
+        "let s = `\u{4}0:1\u{5}\u{E000}` % x\u{4}\nin\n\ndocument(||)'<\n    +p { hello }\n>\n";
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_verbatim_align_trailing_comments() {
+    let text = r#"let s = `あいう` % a
+let x = 1 % b
+in
+document(||)'<+p{hello}>"#;
+
+    let expect = r#"let s = `あいう` % a
+let x = 1        % b
+in
+
+document(||)'<
+    +p { hello }
+>
+"#;
+    let option = FormatOptions {
+        align_trailing_comments: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
@@ -0,0 +1,124 @@
+use super::test_tmpl_with_option;
+use crate::FormatOptions;
+
+fn option(max_width: usize) -> FormatOptions {
+    FormatOptions {
+        max_width,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_width_application() {
+    let text = r#"let x = f aaaaaaaa bbbbbbbb"#;
+
+    let expect = r#"let x = f aaaaaaaa bbbbbbbb
+"#;
+    test_tmpl_with_option(text, expect, option(40));
+
+    let expect = r#"let x =
+    f
+        aaaaaaaa
+        bbbbbbbb
+"#;
+    test_tmpl_with_option(text, expect, option(16));
+}
+
+#[test]
+fn test_width_dyadic() {
+    let text = r#"let x = aaaaaaaa + bbbbbbbb"#;
+
+    let expect = r#"let x =
+    aaaaaaaa
+        + bbbbbbbb
+"#;
+    test_tmpl_with_option(text, expect, option(16));
+}
+
+#[test]
+fn test_width_tuple() {
+    let text = r#"let x = (aaaaaaaa, bbbbbbbb)"#;
+
+    let expect = r#"let x =
+    (
+        aaaaaaaa,
+        bbbbbbbb
+    )
+"#;
+    test_tmpl_with_option(text, expect, option(16));
+}
+
+#[test]
+fn test_width_record() {
+    let text = r#"let r = (|a = 1; b = 2;|)"#;
+
+    let expect = r#"let r = (|a = 1; b = 2|)
+"#;
+    test_tmpl_with_option(text, expect, FormatOptions::default());
+
+    let expect = r#"let r =
+    (|
+        a = 1;
+        b = 2;
+    |)
+"#;
+    test_tmpl_with_option(text, expect, option(10));
+
+    // record_break_fields を指定した場合は収まっても改行する
+    let option = FormatOptions {
+        record_break_fields: Some(1),
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option);
+}
+
+#[test]
+fn test_width_list() {
+    let text = r#"let xs = [aaaa;bbbb;cccc;dddd;]"#;
+
+    let expect = r#"let xs = [aaaa; bbbb; cccc; dddd]
+"#;
+    test_tmpl_with_option(text, expect, FormatOptions::default());
+
+    // 同じ行の前の部分 `let xs = ` も幅に含める
+    let expect = r#"let xs =
+    [aaaa; bbbb; cccc; dddd]
+"#;
+    test_tmpl_with_option(text, expect, option(30));
+
+    let option = FormatOptions {
+        list_break_width: Some(15),
+        ..Default::default()
+    };
+    let expect = r#"let xs =
+    [
+        aaaa;
+        bbbb;
+        cccc;
+        dddd;
+    ]
+"#;
+    test_tmpl_with_option(text, expect, option);
+}
+
+#[test]
+fn test_width_tab() {
+    let text = r#"document(||)'<+foo [aaaa;bbbb];>"#;
+
+    let expect = "document(||)'<\n\t+foo [aaaa; bbbb];\n>\n";
+    let option = FormatOptions {
+        use_tabs: true,
+        tab_width: 8,
+        max_width: 25,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option.clone());
+
+    // タブの幅を含めると収まらない
+    let expect = "document(||)'<\n\t+foo [\n\t\taaaa;\n\t\tbbbb;\n\t];\n>\n";
+    let option = FormatOptions {
+        max_width: 24,
+        ..option
+    };
+    test_tmpl_with_option(text, expect, option);
+}
@@ -4,7 +4,7 @@ use satysfi_parser::{Cst, CstText, Rule};
 /// visualize
 /// * `csttext:` - CstText::parse で取得したものを渡す
 pub fn visualize_csttext_tree(csttext: &CstText) {
-    println!("visualize");
+    eprintln!("visualize");
     visualize_cst_tree(csttext, &csttext.cst, 0);
 }
 
@@ -32,7 +32,7 @@ fn visualize_cst_tree(csttext: &CstText, cst: &Cst, depth: usize) {
             .replace('\n', ""); // 改行を削除
         format!("{}...{}", start_text, end_text)
     };
-    println!(
+    eprintln!(
         "{}* {:?}: {}",
         " ".repeat(depth * 2),
         cst.rule,